@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use rand::Rng;
+
+use rust_territorial::modules::config::{take_flag, GameConfig};
+use rust_territorial::modules::game::GameState;
+use rust_territorial::modules::logging;
+use rust_territorial::modules::simulation::{play_out, run_game, SimulationSummary};
+
+const DEFAULT_MAX_TICKS: u64 = 20_000;
+
+struct SimOptions {
+    max_ticks: u64,
+    games: u64,
+    load_snapshot: Option<String>,
+    save_snapshot: Option<String>,
+    record: Option<String>,
+    game_args: Vec<String>,
+}
+
+impl SimOptions {
+    fn single_game(&self) -> bool {
+        self.load_snapshot.is_some() || self.save_snapshot.is_some() || self.record.is_some()
+    }
+}
+
+fn parse_options(mut args: Vec<String>) -> Result<SimOptions, String> {
+    let load_snapshot = take_flag(&mut args, "--load-snapshot").map_err(|e| e.to_string())?;
+    let save_snapshot = take_flag(&mut args, "--save-snapshot").map_err(|e| e.to_string())?;
+    let record = take_flag(&mut args, "--record").map_err(|e| e.to_string())?;
+    let mut options = SimOptions {
+        max_ticks: DEFAULT_MAX_TICKS,
+        games: 1,
+        load_snapshot,
+        save_snapshot,
+        record,
+        game_args: Vec::new(),
+    };
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--max-ticks" | "--games" => {
+                let value = iter.next().ok_or_else(|| format!("missing value for {}", arg))?;
+                let parsed = value.parse()
+                    .map_err(|_| format!("invalid value '{}' for {}", value, arg))?;
+                if arg == "--max-ticks" {
+                    options.max_ticks = parsed;
+                } else {
+                    options.games = parsed;
+                }
+            }
+            // Everything else is a game configuration flag
+            _ => options.game_args.push(arg),
+        }
+    }
+
+    if options.games != 1 && options.single_game() {
+        return Err("snapshots and recordings can only be used with a single game".into());
+    }
+    Ok(options)
+}
+
+fn exit_with(message: String) -> ! {
+    eprintln!("Error: {}", message);
+    std::process::exit(1);
+}
+
+// Plays a fresh or restored game to the end, saving what was asked for
+fn finish_single_game(mut state: GameState, options: &SimOptions) {
+    eprintln!("Running game from tick {} with seed {} (tick limit {})", state.tick, state.seed, options.max_ticks);
+
+    play_out(&mut state, options.max_ticks, |_| {});
+    if let Some(path) = &options.save_snapshot {
+        state.save_snapshot(Path::new(path)).unwrap_or_else(|e| exit_with(e.to_string()));
+    }
+    if let Some(path) = &options.record {
+        let log = state.replay_log()
+            .unwrap_or_else(|| exit_with("the loaded snapshot was not being recorded".into()));
+        log.save(Path::new(path)).unwrap_or_else(|e| exit_with(e.to_string()));
+    }
+    let summary = SimulationSummary::from_state(&state);
+    println!("{}", serde_json::to_string(&summary).expect("summary serializes"));
+}
+
+fn main() {
+    logging::set_quiet(true);
+
+    let options = parse_options(std::env::args().skip(1).collect()).unwrap_or_else(|e| exit_with(e));
+    // A snapshot brings its own configuration, flags that would change it are refused
+    if let Some(path) = &options.load_snapshot {
+        let mut state = GameState::load_snapshot(Path::new(path)).unwrap_or_else(|e| exit_with(e.to_string()));
+        state.config.apply_runtime_flags(&options.game_args)
+            .unwrap_or_else(|e| exit_with(format!("configuration: {}", e)));
+        finish_single_game(state, &options);
+        return;
+    }
+    let config = GameConfig::from_args(options.game_args.clone())
+        .unwrap_or_else(|e| exit_with(format!("configuration: {}", e)));
+
+    if options.single_game() {
+        let mut state = GameState::from_config(config).unwrap_or_else(|e| exit_with(e.to_string()));
+        state.initialize_players();
+        if options.record.is_some() {
+            state.start_recording();
+        }
+        finish_single_game(state, &options);
+        return;
+    }
+
+    // Consecutive games use consecutive seeds so a batch is reproducible from its first seed
+    let base_seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    eprintln!("Running {} game(s) from seed {} (tick limit {})", options.games, base_seed, options.max_ticks);
+
+    for game in 0..options.games {
+        let config = GameConfig {
+            seed: Some(base_seed.wrapping_add(game)),
+            ..config.clone()
+        };
+        let summary = run_game(config, options.max_ticks).unwrap_or_else(|e| exit_with(e.to_string()));
+        println!("{}", serde_json::to_string(&summary).expect("summary serializes"));
+    }
+}
@@ -0,0 +1,373 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use rand::Rng;
+
+use rust_territorial::modules::config::GameConfig;
+use rust_territorial::modules::logging;
+use rust_territorial::modules::map::{GameMap, MapError};
+use rust_territorial::modules::simulation::{area_gini, gini, run_game_with};
+
+const DEFAULT_MAX_TICKS: u64 = 20_000;
+const DEFAULT_SEEDS: u64 = 10;
+const DEFAULT_SAMPLE_EVERY: u64 = 100;
+
+const CSV_HEADER: &str = "width,height,base_interest_rate,max_resources_multiplier,min_expansion_cost,\
+base_expansion_chance,seed,ticks,finished,winner,leader,leader_start_x,leader_start_y,\
+leader_start_edge_distance,eliminations,gini_mean,gini_final,gini_samples,error";
+
+struct SweepOptions {
+    interest_rates: Option<Vec<f64>>,
+    resource_multipliers: Option<Vec<f64>>,
+    expansion_costs: Option<Vec<f64>>,
+    expansion_chances: Option<Vec<f64>>,
+    grid_sizes: Option<Vec<(usize, usize)>>,
+    seeds: u64,
+    threads: usize,
+    max_ticks: u64,
+    sample_every: u64,
+    output: Option<String>,
+    game_args: Vec<String>,
+}
+
+struct Job {
+    config: GameConfig,
+}
+
+struct JobResult {
+    index: usize,
+    row: String,
+}
+
+// Accepts either a comma separated list ("0.02,0.05") or an inclusive range "start:end:step"
+fn parse_values(flag: &str, spec: &str) -> Result<Vec<f64>, String> {
+    let invalid = || format!("invalid value '{}' for {}", spec, flag);
+
+    if spec.contains(':') {
+        let parts: Vec<f64> = spec.split(':')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let [start, end, step] = parts[..] else {
+            return Err(invalid());
+        };
+        if step <= 0.0 || end < start {
+            return Err(invalid());
+        }
+        // Count steps up front so float accumulation does not drop the last value
+        let steps = ((end - start) / step + 1e-9).floor() as usize;
+        Ok((0..=steps).map(|i| start + i as f64 * step).collect())
+    } else {
+        spec.split(',')
+            .map(|part| part.trim().parse::<f64>().map_err(|_| invalid()))
+            .collect()
+    }
+}
+
+fn parse_grid_sizes(spec: &str) -> Result<Vec<(usize, usize)>, String> {
+    spec.split(',')
+        .map(|size| {
+            let (width, height) = size.trim().split_once('x')
+                .ok_or_else(|| format!("invalid grid size '{}', expected WIDTHxHEIGHT", size))?;
+            let width = width.parse().map_err(|_| format!("invalid grid width in '{}'", size))?;
+            let height = height.parse().map_err(|_| format!("invalid grid height in '{}'", size))?;
+            Ok((width, height))
+        })
+        .collect()
+}
+
+fn parse_options(args: Vec<String>) -> Result<SweepOptions, String> {
+    let mut options = SweepOptions {
+        interest_rates: None,
+        resource_multipliers: None,
+        expansion_costs: None,
+        expansion_chances: None,
+        grid_sizes: None,
+        seeds: DEFAULT_SEEDS,
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        max_ticks: DEFAULT_MAX_TICKS,
+        sample_every: DEFAULT_SAMPLE_EVERY,
+        output: None,
+        game_args: Vec::new(),
+    };
+
+    let mut iter = args.into_iter();
+    while let Some(flag) = iter.next() {
+        let sweep_flag = matches!(flag.as_str(),
+            "--interest-rate" | "--max-resources-multiplier" | "--min-expansion-cost" |
+            "--expansion-chance" | "--grid" | "--seeds" | "--threads" | "--max-ticks" |
+            "--sample-every" | "--output");
+        if !sweep_flag {
+            // Everything else configures the base game shared by all combinations
+            options.game_args.push(flag);
+            continue;
+        }
+
+        let value = iter.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        let invalid = || format!("invalid value '{}' for {}", value, flag);
+        match flag.as_str() {
+            "--interest-rate" => options.interest_rates = Some(parse_values(&flag, &value)?),
+            "--max-resources-multiplier" => options.resource_multipliers = Some(parse_values(&flag, &value)?),
+            "--min-expansion-cost" => options.expansion_costs = Some(parse_values(&flag, &value)?),
+            "--expansion-chance" => options.expansion_chances = Some(parse_values(&flag, &value)?),
+            "--grid" => options.grid_sizes = Some(parse_grid_sizes(&value)?),
+            "--seeds" => options.seeds = value.parse().map_err(|_| invalid())?,
+            "--threads" => options.threads = value.parse::<usize>().map_err(|_| invalid())?.max(1),
+            "--max-ticks" => options.max_ticks = value.parse().map_err(|_| invalid())?,
+            "--sample-every" => options.sample_every = value.parse::<u64>().map_err(|_| invalid())?.max(1),
+            "--output" => options.output = Some(value),
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(options)
+}
+
+fn build_jobs(options: &SweepOptions, base: &GameConfig, base_seed: u64) -> Result<Vec<Job>, String> {
+    let interest_rates = options.interest_rates.clone().unwrap_or_else(|| vec![base.base_interest_rate]);
+    let multipliers = options.resource_multipliers.clone()
+        .unwrap_or_else(|| vec![base.max_resources_multiplier as f64]);
+    let costs = options.expansion_costs.clone().unwrap_or_else(|| vec![base.min_expansion_cost as f64]);
+    let chances = options.expansion_chances.clone().unwrap_or_else(|| vec![base.base_expansion_chance]);
+    let grid_sizes = options.grid_sizes.clone().unwrap_or_else(|| vec![(base.grid_width, base.grid_height)]);
+
+    let mut jobs = Vec::new();
+    for &(grid_width, grid_height) in &grid_sizes {
+        for &base_interest_rate in &interest_rates {
+            for &multiplier in &multipliers {
+                for &cost in &costs {
+                    for &base_expansion_chance in &chances {
+                        let config = GameConfig {
+                            grid_width,
+                            grid_height,
+                            base_interest_rate,
+                            max_resources_multiplier: multiplier.round() as i32,
+                            min_expansion_cost: cost.round() as i32,
+                            base_expansion_chance,
+                            ..base.clone()
+                        };
+                        config.validate().map_err(|e| {
+                            format!("combination {}x{}/{}/{}/{}/{} is not playable: {}",
+                                grid_width, grid_height, base_interest_rate, multiplier, cost,
+                                base_expansion_chance, e)
+                        })?;
+
+                        // Every combination plays the same seeds so outcomes can be compared pairwise
+                        for seed in 0..options.seeds {
+                            jobs.push(Job {
+                                config: GameConfig {
+                                    seed: Some(base_seed.wrapping_add(seed)),
+                                    ..config.clone()
+                                },
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(jobs)
+}
+
+// The swept parameters and the seed, the first columns of every row
+fn config_columns(config: &GameConfig) -> String {
+    format!(
+        "{},{},{},{},{},{},{}",
+        config.grid_width,
+        config.grid_height,
+        config.base_interest_rate,
+        config.max_resources_multiplier,
+        config.min_expansion_cost,
+        config.base_expansion_chance,
+        config.seed.map(|seed| seed.to_string()).unwrap_or_default(),
+    )
+}
+
+// Quotes a field holding separators, quotes or line breaks
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// A game that could not be played keeps its row, with the reason in the last column
+fn error_row(config: &GameConfig, error: &str) -> String {
+    format!("{},,,,,,,,,,,,{}", config_columns(config), csv_field(error))
+}
+
+fn run_job(job: &Job, max_ticks: u64, sample_every: u64) -> Result<String, MapError> {
+    let mut gini_samples = Vec::new();
+    let summary = run_game_with(job.config.clone(), max_ticks, |state| {
+        if state.tick % sample_every == 0 {
+            gini_samples.push(area_gini(state));
+        }
+    })?;
+
+    let config = &job.config;
+    let gini_mean = if gini_samples.is_empty() {
+        0.0
+    } else {
+        gini_samples.iter().sum::<f64>() / gini_samples.len() as f64
+    };
+    let mut final_areas = vec![0i64; config.num_players];
+    for area in &summary.final_areas {
+        final_areas[area.player] = area.area.max(0) as i64;
+    }
+    let gini_final = gini(&mut final_areas);
+    let (start_x, start_y, edge_distance) = match summary.leader_start {
+        Some((x, y)) => {
+            let edge = x.min(y).min(config.grid_width - 1 - x).min(config.grid_height - 1 - y);
+            (x.to_string(), y.to_string(), edge.to_string())
+        }
+        None => (String::new(), String::new(), String::new()),
+    };
+    let optional = |value: Option<usize>| value.map(|v| v.to_string()).unwrap_or_default();
+
+    Ok(format!(
+        "{},{},{},{},{},{},{},{},{},{:.4},{:.4},{},",
+        config_columns(config),
+        summary.ticks,
+        summary.finished,
+        optional(summary.winner),
+        optional(summary.leader),
+        start_x,
+        start_y,
+        edge_distance,
+        summary.eliminations.len(),
+        gini_mean,
+        gini_final,
+        gini_samples.iter().map(|g| format!("{:.4}", g)).collect::<Vec<_>>().join(";"),
+    ))
+}
+
+fn main() {
+    logging::set_quiet(true);
+
+    let options = match parse_options(std::env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut base = match GameConfig::from_args(options.game_args.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Games on a map take its size, load it once up front so the CSV reports it
+    if let Some(name) = &base.map {
+        if options.grid_sizes.is_some() {
+            eprintln!("Error: --grid cannot be swept when playing on a map");
+            std::process::exit(1);
+        }
+        match GameMap::load(name) {
+            Ok(map) => (base.grid_width, base.grid_height) = (map.terrain.width(), map.terrain.height()),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let base_seed = base.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let jobs = match build_jobs(&options, &base, base_seed) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Running {} game(s) on {} thread(s) from seed {} (tick limit {})",
+        jobs.len(), options.threads, base_seed, options.max_ticks);
+
+    let next_job = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..options.threads.min(jobs.len()) {
+            let tx = tx.clone();
+            let (jobs, next_job) = (&jobs, &next_job);
+            let (max_ticks, sample_every) = (options.max_ticks, options.sample_every);
+            scope.spawn(move || loop {
+                let index = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(index) else {
+                    break;
+                };
+                let row = run_job(job, max_ticks, sample_every)
+                    .unwrap_or_else(|e| {
+                        eprintln!("Game {} failed: {}", index, e);
+                        error_row(&job.config, &e.to_string())
+                    });
+                if tx.send(JobResult { index, row }).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+    });
+
+    // Rows are written in combination order regardless of which thread finished first
+    let mut results: Vec<JobResult> = rx.into_iter().collect();
+    results.sort_by_key(|result| result.index);
+
+    let write_result = (|| -> io::Result<()> {
+        let mut out: Box<dyn Write> = match &options.output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(BufWriter::new(io::stdout().lock())),
+        };
+        writeln!(out, "{}", CSV_HEADER)?;
+        for result in &results {
+            writeln!(out, "{}", result.row)?;
+        }
+        out.flush()
+    })();
+
+    if let Err(e) = write_result {
+        eprintln!("Error: could not write results: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_lists_and_ranges_are_parsed() {
+        assert_eq!(parse_values("--seeds", "0.02, 0.05"), Ok(vec![0.02, 0.05]));
+        let range = parse_values("--interest-rate", "0.1:0.3:0.1").unwrap();
+        assert_eq!(range.len(), 3);
+        assert!((range[2] - 0.3).abs() < 1e-9);
+        assert_eq!(parse_values("--min-expansion-cost", "5:5:1"), Ok(vec![5.0]));
+        for spec in ["", "a,b", "1:2", "3:1:1", "0:1:0", "0:1:-1"] {
+            assert!(parse_values("--interest-rate", spec).is_err(), "{} should be rejected", spec);
+        }
+
+        assert_eq!(parse_grid_sizes("200x100, 80x60"), Ok(vec![(200, 100), (80, 60)]));
+        assert!(parse_grid_sizes("200").is_err() && parse_grid_sizes("ax100").is_err());
+    }
+
+    #[test]
+    fn failed_games_keep_a_row() {
+        let config = GameConfig { seed: Some(4), ..GameConfig::default() };
+        let row = error_row(&config, "map \"islands\" has 2 spawns, needs 4");
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count() + 1);
+        assert!(row.starts_with("800,600,0.05,50,5,0.8,4,,"));
+        assert!(row.ends_with("\"map \"\"islands\"\" has 2 spawns, needs 4\""));
+        assert_eq!(csv_field("plain"), "plain");
+
+        let job = Job { config: GameConfig { grid_width: 40, grid_height: 30, num_players: 2, ..config } };
+        let row = run_job(&job, 20, 10).unwrap();
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+        assert!(row.ends_with(','));
+    }
+}
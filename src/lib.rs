@@ -0,0 +1,11 @@
+pub mod modules;
+pub mod web;
+
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use modules::timing::TimingStats;
+
+lazy_static::lazy_static! {
+    pub static ref TIMING_STATS: Arc<RwLock<TimingStats>> = Arc::new(RwLock::new(TimingStats::new()));
+}
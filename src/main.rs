@@ -1,80 +1,107 @@
-mod modules;
-mod web;
-
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::time::{self, Duration};
 use warp::Filter;
 
-use modules::config::*;
+use rust_territorial::{modules, web, TIMING_STATS};
+use modules::config::{take_flag, GameConfig};
 use modules::game::GameState;
-use modules::timing::{TimingStats, start_timing_logger};
-
-lazy_static::lazy_static! {
-    pub static ref TIMING_STATS: Arc<RwLock<TimingStats>> = Arc::new(RwLock::new(TimingStats::new()));
-}
+use modules::timing::start_timing_logger;
 
 #[tokio::main]
 async fn main() {
     println!("Initializing game server...");
-    
-    let game_state = Arc::new(RwLock::new(GameState::new()));
-    game_state.write().await.initialize_players();
 
-    // Start timing logger
-    tokio::spawn(start_timing_logger(TIMING_STATS.clone()));
-
-    // Create broadcast channel with large capacity
-    let (tx, _rx) = tokio::sync::broadcast::channel::<String>(8192);
-    let tx = Arc::new(tx);
-
-    // Keep a receiver around to prevent channel from closing
-    let _keep_alive_rx = tx.subscribe();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let snapshot = match take_flag(&mut args, "--load-snapshot") {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Set up periodic game state updates
-    let game_state_update = game_state.clone();
-    let tx_update = tx.clone();
-    tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_millis(UPDATE_INTERVAL_MS));
-        loop {
-            interval.tick().await;
-            {
-                let _timer = modules::timing::ExecutionTimer::new(TIMING_STATS.clone(), "game_state_update");
-                let mut state = game_state_update.write().await;
-                state.update();
-                
-                // Broadcast updated state
-                if let Ok(state_json) = serde_json::to_string(&state.grid) {
-                    let _ = tx_update.send(state_json);
+    let state = match snapshot {
+        Some(path) => {
+            let mut state = match GameState::load_snapshot(std::path::Path::new(&path)) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("Could not load snapshot: {}", e);
+                    std::process::exit(1);
                 }
+            };
+            if let Err(e) = state.config.apply_runtime_flags(&args) {
+                eprintln!("Configuration error: {}", e);
+                std::process::exit(1);
             }
-
-            // Small delay to prevent tight loops
-            tokio::time::sleep(Duration::from_millis(5)).await;
+            state
         }
-    });
+        None => {
+            let config = match GameConfig::from_args(args) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Configuration error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let mut state = match GameState::from_config(config) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("Could not load map: {}", e);
+                    let maps = modules::map::list_maps();
+                    if !maps.is_empty() {
+                        eprintln!("Available maps: {}", maps.join(", "));
+                    }
+                    std::process::exit(1);
+                }
+            };
+            state.initialize_players();
+            state.start_recording();
+            state.open_lobby();
+            state
+        }
+    };
+    let update_interval_ms = state.config.update_interval_ms;
 
-    let game_state_ws = game_state.clone();
-    let tx_ws = tx.clone();
+    println!("Server configuration:");
+    if let Some(map) = &state.config.map {
+        println!("  - Map: {}", map);
+    } else if state.config.generate_map {
+        println!("  - Map: generated ({} continents, {:.0}% land)", state.config.continents, state.config.land_ratio * 100.0);
+    }
+    println!("  - Grid size: {}x{}", state.config.grid_width, state.config.grid_height);
+    println!("  - Number of players: {}", state.config.num_players);
+    println!("  - Default strategy: {}", state.config.default_strategy);
+    if !state.config.strategies.is_empty() {
+        let strategies: Vec<String> = state.config.strategies.iter().map(|kind| kind.to_string()).collect();
+        println!("  - Player strategies: {}", strategies.join(", "));
+    }
+    println!("  - Update interval: {}ms", update_interval_ms);
+    println!(
+        "  - Lobby/countdown/restart: {}/{}/{} ticks",
+        state.config.lobby_ticks, state.config.countdown_ticks, state.config.restart_ticks
+    );
+    println!("  - Seed: {}", state.seed);
+    println!("  - Performance monitoring interval: 60s");
+    
+    // Start timing logger
+    tokio::spawn(start_timing_logger(TIMING_STATS.clone()));
 
-    let ws_route = warp::path("ws")
-        .and(warp::ws())
-        .map(move |ws: warp::ws::Ws| {
-            let game_state = game_state_ws.clone();
-            let tx = tx_ws.clone();
-            ws.on_upgrade(move |socket| web::handle_websocket(socket, game_state, tx))
-        });
+    // The game set up above is hosted in the default room behind `/ws`, more
+    // rooms are opened through `/api/rooms`
+    let rooms = Arc::new(web::rooms::RoomManager::new(state.config.clone()));
+    let default_room = rooms.open_default(state).await;
+    tokio::spawn(web::rooms::run_reaper(rooms.clone()));
+    let matchmaker = Arc::new(web::lobby::Matchmaker::new(rooms.clone()));
+    tokio::spawn(web::lobby::run_matchmaker(matchmaker.clone()));
 
     // Serve static files from the web directory
     let content_route = warp::fs::dir("src/web");
 
-    let routes = content_route.or(ws_route);
+    let admin_routes = web::admin::routes(default_room.state.clone());
+
+    let routes = web::rooms::routes(rooms)
+        .or(web::lobby::routes(matchmaker))
+        .or(content_route).or(admin_routes).or(web::replay::routes());
 
-    println!("Server configuration:");
-    println!("  - Grid size: {}x{}", GRID_WIDTH, GRID_HEIGHT);
-    println!("  - Number of players: {}", NUM_PLAYERS);
-    println!("  - Update interval: {}ms", UPDATE_INTERVAL_MS);
-    println!("  - Performance monitoring interval: 60s");
     println!("\nServer starting on http://localhost:3030");
     
     warp::serve(routes)
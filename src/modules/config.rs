@@ -1,14 +1,482 @@
-// Grid configuration
-pub const GRID_WIDTH: usize = 800;
-pub const GRID_HEIGHT: usize = 600;
-pub const NUM_PLAYERS: usize = 20;
-pub const MIN_AREA_THRESHOLD: i32 = 1;
-
-// Player configuration
-pub const BASE_INTEREST_RATE: f64 = 0.05;  // Increased from 0.01 for faster resource gain
-pub const MAX_RESOURCES_MULTIPLIER: i32 = 50;  // Decreased from 100 to encourage spending
-pub const MIN_EXPANSION_COST: i32 = 5;  // Decreased from 10 for more frequent expansions
-pub const BASE_EXPANSION_CHANCE: f64 = 0.8;  // Increased from 0.5 for more aggressive expansion
-
-// Game update configuration
-pub const UPDATE_INTERVAL_MS: u64 = 100;  // Decreased from 1000 for faster gameplay
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::modules::strategy::StrategyKind;
+
+// Radius kept free around each starting position during placement
+pub const PLACEMENT_RADIUS: usize = 3;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    // Grid configuration, the size is taken from the map image when `map` is set
+    pub map: Option<String>,
+    pub grid_width: usize,
+    pub grid_height: usize,
+    pub num_players: usize,
+    pub min_area_threshold: i32,
+
+    // Procedural terrain, used instead of a blank grid when `generate_map` is set
+    pub generate_map: bool,
+    // Share of the grid that ends up as land
+    pub land_ratio: f64,
+    pub continents: usize,
+    pub rivers: usize,
+
+    // Player configuration
+    pub starting_resources: i32,
+    pub base_interest_rate: f64,
+    pub max_resources_multiplier: i32,
+    pub min_expansion_cost: i32,
+    pub base_expansion_chance: f64,
+
+    // Terrain configuration, investment spent per captured mountain pixel
+    pub mountain_cost: i32,
+
+    // Naval invasions: water cells a boat crosses per tick, and the investment
+    // it loses per water cell crossed
+    pub boat_speed: usize,
+    pub naval_cost_per_cell: i32,
+
+    // Attacks aimed at a point only take this many frontier pixels per tick,
+    // the ones closest to the point
+    pub targeted_front_width: usize,
+
+    // Combat: taking an enemy pixel costs an extra `defense_factor` times the
+    // defender's resources per pixel, at most `max_defense_cost`. The defender
+    // loses the same amount, empty land has no defense.
+    pub defense_factor: f64,
+    pub max_defense_cost: i32,
+
+    // Pockets walled in by a single nation go to it, searches give up past this
+    // many cells. 0 turns enclave capture off.
+    pub max_enclave_size: usize,
+
+    // Territory cut off from a nation's core (the part holding its capital, or
+    // its largest part) earns nothing, and its edge cells go neutral with this
+    // chance per tick. Off by default.
+    pub fragment_decay: bool,
+    pub fragment_decay_chance: f64,
+
+    // Diplomacy: ticks a non-aggression pact lasts, and whether the game ends
+    // once every nation left is allied
+    pub pact_duration: u64,
+    pub alliance_victory: bool,
+
+    // Teams, `teams[i]` is the team of player i and players past the end of the
+    // list play alone. Teammates never attack each other and win together.
+    pub teams: Vec<usize>,
+
+    // Server game lifecycle, in update ticks: time in the lobby and counting down
+    // before a game starts, and the pause after it ends before the next one.
+    // A restart delay of 0 keeps the finished game up.
+    pub lobby_ticks: u64,
+    pub countdown_ticks: u64,
+    pub restart_ticks: u64,
+
+    // Win conditions on top of being the last nation or team standing: holding
+    // this share of the land, and being the largest nation when the time limit
+    // is reached. 0 turns either off.
+    pub victory_land_share: f64,
+    pub time_limit_ticks: u64,
+
+    // Matchmaking: the lobby opens a room once this many players queue, or when
+    // the first of them has waited `match_timeout_ms`. Bots take the other nations.
+    pub match_players: usize,
+    pub match_timeout_ms: u64,
+
+    // Bot behaviour, `strategies[i]` drives player i and the others use `default_strategy`
+    pub default_strategy: StrategyKind,
+    pub strategies: Vec<StrategyKind>,
+
+    // Game update configuration
+    pub update_interval_ms: u64,
+
+    // Simulation seed, a random one is picked (and logged) when unset
+    pub seed: Option<u64>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            map: None,
+            grid_width: 800,
+            grid_height: 600,
+            num_players: 20,
+            min_area_threshold: 1,
+            generate_map: false,
+            land_ratio: 0.45,
+            continents: 3,
+            rivers: 4,
+            starting_resources: 1000,
+            base_interest_rate: 0.05,  // Increased from 0.01 for faster resource gain
+            max_resources_multiplier: 50,  // Decreased from 100 to encourage spending
+            min_expansion_cost: 5,  // Decreased from 10 for more frequent expansions
+            base_expansion_chance: 0.8,  // Increased from 0.5 for more aggressive expansion
+            mountain_cost: 3,
+            boat_speed: 3,
+            naval_cost_per_cell: 1,
+            targeted_front_width: 12,
+            defense_factor: 0.1,
+            max_defense_cost: 10,
+            max_enclave_size: 5000,
+            fragment_decay: false,
+            fragment_decay_chance: 0.1,
+            pact_duration: 600,
+            alliance_victory: false,
+            teams: Vec::new(),
+            lobby_ticks: 0,
+            countdown_ticks: 30,
+            restart_ticks: 100,
+            victory_land_share: 0.0,
+            time_limit_ticks: 0,
+            match_players: 4,
+            match_timeout_ms: 30_000,
+            default_strategy: StrategyKind::Random,
+            strategies: Vec::new(),
+            update_interval_ms: 100,  // Decreased from 1000 for faster gameplay
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    UnsupportedFormat(PathBuf),
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "could not parse {}: {}", path.display(), err),
+            ConfigError::UnsupportedFormat(path) => {
+                write!(f, "unsupported config format for {} (expected .toml or .json)", path.display())
+            }
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown flag {}", flag),
+            ConfigError::MissingValue(flag) => write!(f, "missing value for {}", flag),
+            ConfigError::InvalidValue { flag, value } => write!(f, "invalid value '{}' for {}", value, flag),
+            ConfigError::Invalid(reason) => write!(f, "invalid configuration: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl GameConfig {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)
+                .map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string())),
+            Some("json") => serde_json::from_str(&contents)
+                .map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string())),
+            _ => Err(ConfigError::UnsupportedFormat(path.to_path_buf())),
+        }
+    }
+
+    // Builds the configuration from `--config <file>` followed by individual flag overrides
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let args: Vec<String> = args.into_iter().collect();
+
+        let mut config = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => {
+                let path = args.get(i + 1).ok_or_else(|| ConfigError::MissingValue("--config".into()))?;
+                GameConfig::from_file(Path::new(path))?
+            }
+            None => GameConfig::default(),
+        };
+
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let value = iter.next().ok_or_else(|| ConfigError::MissingValue(flag.clone()))?;
+            match flag.as_str() {
+                "--config" => {}
+                "--map" => config.map = Some(value.clone()),
+                "--width" => config.grid_width = parse_flag(flag, value)?,
+                "--height" => config.grid_height = parse_flag(flag, value)?,
+                "--players" => config.num_players = parse_flag(flag, value)?,
+                "--generate-map" => config.generate_map = parse_flag(flag, value)?,
+                "--land-ratio" => config.land_ratio = parse_flag(flag, value)?,
+                "--continents" => config.continents = parse_flag(flag, value)?,
+                "--rivers" => config.rivers = parse_flag(flag, value)?,
+                "--min-area" => config.min_area_threshold = parse_flag(flag, value)?,
+                "--starting-resources" => config.starting_resources = parse_flag(flag, value)?,
+                "--interest-rate" => config.base_interest_rate = parse_flag(flag, value)?,
+                "--max-resources-multiplier" => config.max_resources_multiplier = parse_flag(flag, value)?,
+                "--min-expansion-cost" => config.min_expansion_cost = parse_flag(flag, value)?,
+                "--expansion-chance" => config.base_expansion_chance = parse_flag(flag, value)?,
+                "--mountain-cost" => config.mountain_cost = parse_flag(flag, value)?,
+                "--boat-speed" => config.boat_speed = parse_flag(flag, value)?,
+                "--naval-cost" => config.naval_cost_per_cell = parse_flag(flag, value)?,
+                "--targeted-front-width" => config.targeted_front_width = parse_flag(flag, value)?,
+                "--defense-factor" => config.defense_factor = parse_flag(flag, value)?,
+                "--max-defense-cost" => config.max_defense_cost = parse_flag(flag, value)?,
+                "--max-enclave-size" => config.max_enclave_size = parse_flag(flag, value)?,
+                "--fragment-decay" => config.fragment_decay = parse_flag(flag, value)?,
+                "--fragment-decay-chance" => config.fragment_decay_chance = parse_flag(flag, value)?,
+                "--pact-duration" => config.pact_duration = parse_flag(flag, value)?,
+                "--alliance-victory" => config.alliance_victory = parse_flag(flag, value)?,
+                "--teams" => config.teams = value.split(',')
+                    .map(|team| parse_flag(flag, team.trim()))
+                    .collect::<Result<_, _>>()?,
+                "--lobby-ticks" => config.lobby_ticks = parse_flag(flag, value)?,
+                "--countdown-ticks" => config.countdown_ticks = parse_flag(flag, value)?,
+                "--restart-ticks" => config.restart_ticks = parse_flag(flag, value)?,
+                "--victory-land-share" => config.victory_land_share = parse_flag(flag, value)?,
+                "--time-limit" => config.time_limit_ticks = parse_flag(flag, value)?,
+                "--match-players" => config.match_players = parse_flag(flag, value)?,
+                "--match-timeout" => config.match_timeout_ms = parse_flag(flag, value)?,
+                "--strategy" => config.default_strategy = parse_flag(flag, value)?,
+                "--strategies" => config.strategies = value.split(',')
+                    .map(|kind| parse_flag(flag, kind.trim()))
+                    .collect::<Result<_, _>>()?,
+                "--update-interval" => config.update_interval_ms = parse_flag(flag, value)?,
+                "--seed" => config.seed = Some(parse_flag(flag, value)?),
+                _ => return Err(ConfigError::UnknownFlag(flag.clone())),
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Games restored from a snapshot keep their own configuration, only the
+    // flags for how the server runs them apply
+    pub fn apply_runtime_flags(&mut self, args: &[String]) -> Result<(), ConfigError> {
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let value = iter.next().ok_or_else(|| ConfigError::MissingValue(flag.clone()))?;
+            match flag.as_str() {
+                "--update-interval" => self.update_interval_ms = parse_flag(flag, value)?,
+                "--match-players" => self.match_players = parse_flag(flag, value)?,
+                "--match-timeout" => self.match_timeout_ms = parse_flag(flag, value)?,
+                _ => return Err(ConfigError::Invalid(format!(
+                    "{} cannot be combined with a snapshot, the game keeps the configuration it was saved with", flag
+                ))),
+            }
+        }
+        self.validate()
+    }
+
+    pub fn strategy_for(&self, player_id: usize) -> StrategyKind {
+        self.strategies.get(player_id).copied().unwrap_or(self.default_strategy)
+    }
+
+    pub fn team_for(&self, player_id: usize) -> Option<usize> {
+        self.teams.get(player_id).copied()
+    }
+
+    // Spacing and section counts used by initialize_players to spread nations over the grid
+    pub fn placement_sections(&self) -> (usize, usize, usize) {
+        let min_spacing = PLACEMENT_RADIUS * 2 + 1;
+        let min_distance = (self.grid_width * self.grid_height / self.num_players.max(1)) as f64;
+        let mut spacing = (min_distance.sqrt() as usize).max(min_spacing);
+
+        // Shrink sections until every player gets one, the square-root estimate
+        // leaves remainders on both axes unused
+        while spacing > min_spacing
+            && (self.grid_width / spacing) * (self.grid_height / spacing) < self.num_players
+        {
+            spacing -= 1;
+        }
+        (spacing, self.grid_width / spacing, self.grid_height / spacing)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_settings()?;
+        // Map files bring their own size and usually their own spawn points,
+        // GameState::with_map checks placement once the map is loaded
+        if self.map.is_none() {
+            self.check_placement()?;
+        }
+        Ok(())
+    }
+
+    // Whether every nation gets a placement section, for grids without spawn points
+    pub fn check_placement(&self) -> Result<(), ConfigError> {
+        let (spacing, sections_x, sections_y) = self.placement_sections();
+        if sections_x * sections_y < self.num_players {
+            return Err(ConfigError::Invalid(format!(
+                "{} players do not fit on a {}x{} grid ({} placement sections of {}px)",
+                self.num_players, self.grid_width, self.grid_height, sections_x * sections_y, spacing
+            )));
+        }
+        Ok(())
+    }
+
+    // Everything validate checks but the placement of nations
+    pub(crate) fn validate_settings(&self) -> Result<(), ConfigError> {
+        let min_side = PLACEMENT_RADIUS * 2 + 1;
+        if self.grid_width < min_side || self.grid_height < min_side {
+            return Err(ConfigError::Invalid(format!(
+                "grid must be at least {}x{}, got {}x{}",
+                min_side, min_side, self.grid_width, self.grid_height
+            )));
+        }
+        // The binary wire protocol stores sizes and owner ids as u16
+        if self.grid_width > u16::MAX as usize || self.grid_height > u16::MAX as usize {
+            return Err(ConfigError::Invalid(format!("grid sides must not exceed {}", u16::MAX)));
+        }
+        if self.num_players == 0 {
+            return Err(ConfigError::Invalid("num_players must be at least 1".into()));
+        }
+        if self.num_players >= u16::MAX as usize {
+            return Err(ConfigError::Invalid(format!("num_players must be below {}", u16::MAX)));
+        }
+
+        if self.map.is_some() && self.generate_map {
+            return Err(ConfigError::Invalid("map and generate_map cannot be combined".into()));
+        }
+        if !(self.land_ratio > 0.05 && self.land_ratio <= 0.95) {
+            return Err(ConfigError::Invalid("land_ratio must be between 0.05 and 0.95".into()));
+        }
+        if self.continents == 0 {
+            return Err(ConfigError::Invalid("continents must be at least 1".into()));
+        }
+
+        if self.starting_resources < 0 {
+            return Err(ConfigError::Invalid("starting_resources must not be negative".into()));
+        }
+        if self.base_interest_rate.is_nan() || self.base_interest_rate < 0.0 {
+            return Err(ConfigError::Invalid("base_interest_rate must not be negative".into()));
+        }
+        if self.max_resources_multiplier <= 0 {
+            return Err(ConfigError::Invalid("max_resources_multiplier must be positive".into()));
+        }
+        if self.min_expansion_cost < 0 {
+            return Err(ConfigError::Invalid("min_expansion_cost must not be negative".into()));
+        }
+        if !(0.0..=1.0).contains(&self.base_expansion_chance) {
+            return Err(ConfigError::Invalid("base_expansion_chance must be between 0 and 1".into()));
+        }
+        if self.mountain_cost < 1 {
+            return Err(ConfigError::Invalid("mountain_cost must be at least 1".into()));
+        }
+        if self.boat_speed == 0 {
+            return Err(ConfigError::Invalid("boat_speed must be positive".into()));
+        }
+        if self.naval_cost_per_cell < 0 {
+            return Err(ConfigError::Invalid("naval_cost_per_cell must not be negative".into()));
+        }
+        if self.targeted_front_width == 0 {
+            return Err(ConfigError::Invalid("targeted_front_width must be positive".into()));
+        }
+        if self.defense_factor.is_nan() || self.defense_factor < 0.0 || self.max_defense_cost < 0 {
+            return Err(ConfigError::Invalid("defense_factor and max_defense_cost must not be negative".into()));
+        }
+        if !(0.0..=1.0).contains(&self.fragment_decay_chance) {
+            return Err(ConfigError::Invalid("fragment_decay_chance must be between 0 and 1".into()));
+        }
+        if self.pact_duration == 0 {
+            return Err(ConfigError::Invalid("pact_duration must be positive".into()));
+        }
+        if !(0.0..=1.0).contains(&self.victory_land_share) {
+            return Err(ConfigError::Invalid("victory_land_share must be between 0 and 1".into()));
+        }
+        if self.match_players == 0 {
+            return Err(ConfigError::Invalid("match_players must be at least 1".into()));
+        }
+        if self.teams.len() > self.num_players {
+            return Err(ConfigError::Invalid(format!(
+                "{} teams given for {} players", self.teams.len(), self.num_players
+            )));
+        }
+        if self.strategies.len() > self.num_players {
+            return Err(ConfigError::Invalid(format!(
+                "{} strategies given for {} players", self.strategies.len(), self.num_players
+            )));
+        }
+        if self.update_interval_ms == 0 {
+            return Err(ConfigError::Invalid("update_interval_ms must be positive".into()));
+        }
+        Ok(())
+    }
+}
+
+// Removes `flag <value>` from `args`, for options handled by a binary rather than GameConfig
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, ConfigError> {
+    let Some(i) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(ConfigError::MissingValue(flag.to_string()));
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(value))
+}
+
+fn parse_flag<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let path = std::env::temp_dir().join(format!("territorial-config-{}.toml", std::process::id()));
+        fs::write(&path, "num_players = 8\ngrid_width = 200\nstarting_resources = 500\n").unwrap();
+        let line = format!("--players 12 --config {} --seed 3", path.display());
+        let config = GameConfig::from_args(args(&line));
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        // Flags win wherever they appear, the file fills in the rest over the defaults
+        assert_eq!((config.num_players, config.grid_width, config.starting_resources), (12, 200, 500));
+        assert_eq!((config.grid_height, config.seed), (GameConfig::default().grid_height, Some(3)));
+
+        let json = std::env::temp_dir().join(format!("territorial-config-{}.json", std::process::id()));
+        fs::write(&json, r#"{ "num_players": 6 }"#).unwrap();
+        let config = GameConfig::from_file(&json);
+        fs::remove_file(&json).unwrap();
+        assert_eq!(config.unwrap().num_players, 6);
+        assert!(matches!(GameConfig::from_file(Path::new("game.yaml")), Err(ConfigError::Io(..))));
+    }
+
+    #[test]
+    fn bad_flags_and_values_are_rejected() {
+        assert!(matches!(GameConfig::from_args(args("--colour red")), Err(ConfigError::UnknownFlag(flag)) if flag == "--colour"));
+        assert!(matches!(GameConfig::from_args(args("--players")), Err(ConfigError::MissingValue(_))));
+        assert!(matches!(GameConfig::from_args(args("--players many")), Err(ConfigError::InvalidValue { .. })));
+        assert!(matches!(GameConfig::from_args(args("--config")), Err(ConfigError::MissingValue(_))));
+
+        let invalid = |config: GameConfig| matches!(config.validate(), Err(ConfigError::Invalid(_)));
+        assert!(GameConfig::default().validate().is_ok());
+        assert!(invalid(GameConfig { grid_width: 4, ..GameConfig::default() }));
+        assert!(invalid(GameConfig { num_players: 0, ..GameConfig::default() }));
+        assert!(invalid(GameConfig { grid_width: 40, grid_height: 40, num_players: 50, ..GameConfig::default() }));
+        assert!(invalid(GameConfig { base_interest_rate: f64::NAN, ..GameConfig::default() }));
+        assert!(invalid(GameConfig { base_expansion_chance: 1.5, ..GameConfig::default() }));
+        assert!(invalid(GameConfig { defense_factor: f64::NAN, ..GameConfig::default() }));
+        assert!(invalid(GameConfig { update_interval_ms: 0, ..GameConfig::default() }));
+        assert!(invalid(GameConfig { teams: vec![0; 21], ..GameConfig::default() }));
+    }
+
+    #[test]
+    fn snapshots_only_take_runtime_flags() {
+        let mut config = GameConfig { num_players: 5, ..GameConfig::default() };
+        config.apply_runtime_flags(&args("--update-interval 250 --match-players 2")).unwrap();
+        assert_eq!((config.update_interval_ms, config.match_players, config.num_players), (250, 2, 5));
+        assert!(matches!(config.apply_runtime_flags(&args("--players 8")), Err(ConfigError::Invalid(_))));
+        assert!(matches!(config.apply_runtime_flags(&args("--update-interval 0")), Err(ConfigError::Invalid(_))));
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::modules::timing::ExecutionTimer;
+use crate::modules::types::PlayerKind;
+use crate::TIMING_STATS;
+use super::diplomacy::Treaty;
+use super::state::GameState;
+
+// Orders a human sends for the nation it controls. Claimed nations are skipped by
+// the bot logic in process_player_updates until they are released again.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlayerCommand {
+    Claim,
+    Release,
+    // `target` is a neighbouring nation, or empty land when absent. With `toward`
+    // the attack pushes for that cell instead of the whole border.
+    Attack {
+        target: Option<usize>,
+        percent: f64,
+        #[serde(default)]
+        toward: Option<(usize, usize)>,
+    },
+    // Sends a boat across water to land on the coastal cell (x, y)
+    NavalAttack { x: usize, y: usize, percent: f64 },
+    ProposeTreaty { to: usize, treaty: Treaty },
+    AcceptTreaty { from: usize },
+    BreakTreaty { with: usize },
+}
+
+// Commands are applied between ticks, `tick` is the tick the game was at when
+// the command arrived, so replays apply it right before running tick + 1
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommandRecord {
+    pub tick: u64,
+    pub player: usize,
+    pub command: PlayerCommand,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    NoPlayer,
+    NotInGame(usize),
+    AlreadyClaimed(usize),
+    NotClaimed(usize),
+    InvalidPercent(f64),
+    NotEnoughResources { investment: i32, minimum: i32 },
+    OwnTerritory,
+    NotBordering(usize),
+    NoEmptyLand,
+    NoSeaRoute(usize, usize),
+    OutOfBounds(usize, usize),
+    AtPeace(usize),
+    SelfTreaty,
+    AlreadyAtPeace(usize),
+    NoProposal(usize),
+    NoTreaty(usize),
+    Teammate(usize),
+    NotRunning,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NoPlayer => write!(f, "claim a player first"),
+            CommandError::NotInGame(player) => write!(f, "player {} is not in the game", player),
+            CommandError::AlreadyClaimed(player) => write!(f, "player {} is already controlled by someone", player),
+            CommandError::NotClaimed(player) => write!(f, "player {} is not claimed", player),
+            CommandError::InvalidPercent(percent) => write!(f, "percent must be in (0, 100], got {}", percent),
+            CommandError::NotEnoughResources { investment, minimum } => write!(
+                f, "an investment of {} is below the minimum of {}", investment, minimum
+            ),
+            CommandError::OwnTerritory => write!(f, "cannot attack your own territory"),
+            CommandError::NotBordering(target) => write!(f, "player {} does not border your territory", target),
+            CommandError::NoEmptyLand => write!(f, "no empty land borders your territory"),
+            CommandError::NoSeaRoute(x, y) => write!(f, "no boat from your coast can land at ({}, {})", x, y),
+            CommandError::OutOfBounds(x, y) => write!(f, "({}, {}) is outside the map", x, y),
+            CommandError::AtPeace(player) => write!(f, "you are at peace with player {}", player),
+            CommandError::SelfTreaty => write!(f, "cannot sign a treaty with yourself"),
+            CommandError::AlreadyAtPeace(player) => write!(f, "you already have that treaty with player {}", player),
+            CommandError::NoProposal(player) => write!(f, "player {} has not offered you a treaty", player),
+            CommandError::NoTreaty(player) => write!(f, "you have no treaty with player {}", player),
+            CommandError::Teammate(player) => write!(f, "player {} is on your team", player),
+            CommandError::NotRunning => write!(f, "the game is not running"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl GameState {
+    pub fn is_human(&self, player_id: usize) -> bool {
+        self.human_players.contains(&player_id)
+    }
+
+    // Validates and applies a command, successful ones are added to the recording
+    pub fn apply_command(&mut self, player_id: usize, command: PlayerCommand) -> Result<(), CommandError> {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "apply_command");
+        match command {
+            PlayerCommand::Claim => {
+                if !self.players.iter().any(|p| p.id == player_id) {
+                    return Err(CommandError::NotInGame(player_id));
+                }
+                if !self.human_players.insert(player_id) {
+                    return Err(CommandError::AlreadyClaimed(player_id));
+                }
+                self.set_kind(player_id, PlayerKind::Human);
+            }
+            PlayerCommand::Release => {
+                if !self.human_players.remove(&player_id) {
+                    return Err(CommandError::NotClaimed(player_id));
+                }
+                self.set_kind(player_id, PlayerKind::Bot);
+            }
+            PlayerCommand::Attack { target, percent, toward } => {
+                let investment = self.validate_attack(player_id, target, percent)?;
+                if let Some((x, y)) = toward {
+                    if x >= self.config.grid_width || y >= self.config.grid_height {
+                        return Err(CommandError::OutOfBounds(x, y));
+                    }
+                }
+                // Record first so the attack record follows the command that caused it
+                self.record_command(player_id, command);
+                self.start_attack(player_id, target.unwrap_or(usize::MAX), investment, toward);
+                return Ok(());
+            }
+            PlayerCommand::ProposeTreaty { to, treaty } => {
+                self.validate_human(player_id)?;
+                self.propose_treaty(player_id, to, treaty)?;
+            }
+            PlayerCommand::AcceptTreaty { from } => {
+                self.validate_human(player_id)?;
+                self.accept_treaty(player_id, from)?;
+            }
+            PlayerCommand::BreakTreaty { with } => {
+                self.validate_human(player_id)?;
+                self.break_treaty(player_id, with)?;
+            }
+            PlayerCommand::NavalAttack { x, y, percent } => {
+                let investment = self.validate_naval_attack(player_id, x, y, percent)?;
+                self.record_command(player_id, command);
+                self.start_naval_attack(player_id, x, y, investment);
+                return Ok(());
+            }
+        }
+        self.record_command(player_id, command);
+        Ok(())
+    }
+
+    fn validate_attack(&self, player_id: usize, target: Option<usize>, percent: f64) -> Result<i32, CommandError> {
+        let investment = self.validate_investment(player_id, percent)?;
+        let (neighbors, has_empty_space) = self.border_neighbors(player_id);
+        match target {
+            Some(target) if target == player_id => Err(CommandError::OwnTerritory),
+            Some(target) if self.teammates(player_id, target) => Err(CommandError::Teammate(target)),
+            Some(target) if self.diplomacy.at_peace(player_id, target) => Err(CommandError::AtPeace(target)),
+            Some(target) if !neighbors.contains_key(&target) => Err(CommandError::NotBordering(target)),
+            None if !has_empty_space => Err(CommandError::NoEmptyLand),
+            _ => Ok(investment),
+        }
+    }
+
+    fn validate_naval_attack(&self, player_id: usize, x: usize, y: usize, percent: f64) -> Result<i32, CommandError> {
+        let investment = self.validate_investment(player_id, percent)?;
+        let in_bounds = x < self.config.grid_width && y < self.config.grid_height;
+        if in_bounds && self.grid[y][x] == Some(player_id) {
+            return Err(CommandError::OwnTerritory);
+        }
+        let landing = if in_bounds { self.sea_routes(player_id).landing(x, y).copied() } else { None };
+        let landing = landing.ok_or(CommandError::NoSeaRoute(x, y))?;
+        if let Some(owner) = landing.owner.filter(|&owner| self.teammates(player_id, owner)) {
+            return Err(CommandError::Teammate(owner));
+        }
+        if let Some(owner) = landing.owner.filter(|&owner| self.diplomacy.at_peace(player_id, owner)) {
+            return Err(CommandError::AtPeace(owner));
+        }
+
+        // The boat has to arrive with something left to land
+        let minimum = landing.distance as i32 * self.config.naval_cost_per_cell + 1;
+        if investment < minimum {
+            return Err(CommandError::NotEnoughResources { investment, minimum });
+        }
+        Ok(investment)
+    }
+
+    fn validate_human(&self, player_id: usize) -> Result<(), CommandError> {
+        if !self.players.iter().any(|p| p.id == player_id) {
+            return Err(CommandError::NotInGame(player_id));
+        }
+        if !self.is_human(player_id) {
+            return Err(CommandError::NotClaimed(player_id));
+        }
+        Ok(())
+    }
+
+    // The share of the player's resources a command invests, checked against the
+    // minimum cost. Attacks are only launched while the game runs.
+    fn validate_investment(&self, player_id: usize, percent: f64) -> Result<i32, CommandError> {
+        if !self.is_running() {
+            return Err(CommandError::NotRunning);
+        }
+        self.validate_human(player_id)?;
+        let player = self.players.iter()
+            .find(|p| p.id == player_id)
+            .ok_or(CommandError::NotInGame(player_id))?;
+        if !(percent > 0.0 && percent <= 100.0) {
+            return Err(CommandError::InvalidPercent(percent));
+        }
+
+        let investment = (player.resources as f64 * percent / 100.0) as i32;
+        let minimum = self.config.min_expansion_cost.max(1);
+        if investment < minimum {
+            return Err(CommandError::NotEnoughResources { investment, minimum });
+        }
+        Ok(investment)
+    }
+
+    fn record_command(&mut self, player: usize, command: PlayerCommand) {
+        if let Some(recording) = &mut self.recording {
+            recording.commands.push(CommandRecord { tick: self.tick, player, command });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::game::testing::{seeded_game, test_config, test_game};
+
+    fn new_game() -> GameState {
+        test_game(60, 40, 2, 9)
+    }
+
+    #[test]
+    fn attacks_are_validated() {
+        let mut state = new_game();
+        let attack = |target, percent| PlayerCommand::Attack { target, percent, toward: None };
+
+        assert_eq!(state.apply_command(0, attack(None, 50.0)), Err(CommandError::NotClaimed(0)));
+        assert_eq!(state.apply_command(7, PlayerCommand::Claim), Err(CommandError::NotInGame(7)));
+        state.apply_command(0, PlayerCommand::Claim).unwrap();
+        assert_eq!(state.apply_command(0, PlayerCommand::Claim), Err(CommandError::AlreadyClaimed(0)));
+
+        assert_eq!(state.apply_command(0, attack(None, 0.0)), Err(CommandError::InvalidPercent(0.0)));
+        assert_eq!(state.apply_command(0, attack(None, 150.0)), Err(CommandError::InvalidPercent(150.0)));
+        assert_eq!(state.apply_command(0, attack(Some(0), 50.0)), Err(CommandError::OwnTerritory));
+        // The two starting territories are far apart
+        assert_eq!(state.apply_command(0, attack(Some(1), 50.0)), Err(CommandError::NotBordering(1)));
+
+        let resources = state.players[0].resources;
+        state.apply_command(0, attack(None, 50.0)).unwrap();
+        assert_eq!(state.players[0].resources, resources - resources / 2);
+        assert_eq!(state.attack_movements.len(), 1);
+    }
+
+    #[test]
+    fn claimed_players_are_left_to_humans() {
+        let mut state = new_game();
+        state.apply_command(1, PlayerCommand::Claim).unwrap();
+        state.update();
+        let area = state.players[1].area;
+        for _ in 0..30 {
+            state.update();
+        }
+        assert!(state.attack_movements.iter().all(|attack| attack.source != 1));
+        assert_eq!(state.players[1].area, area);
+    }
+
+    #[test]
+    fn teammates_cannot_be_attacked() {
+        let mut state = seeded_game(GameConfig { teams: vec![0, 0], ..test_config(60, 40, 3, 9) });
+        state.apply_command(0, PlayerCommand::Claim).unwrap();
+        // A cell of player 1 right next to player 0's starting territory
+        let home = state.players.iter().find(|p| p.id == 0).map(|p| (p.x, p.y)).unwrap();
+        let (x, y) = if home.0 + 2 < 60 { (home.0 + 2, home.1) } else { (home.0 - 2, home.1) };
+        state.grid[y][x] = Some(1);
+        assert!(state.border_neighbors(0).0.contains_key(&1));
+
+        let resources = state.players.iter().find(|p| p.id == 0).unwrap().resources;
+        let attack = |target| PlayerCommand::Attack { target: Some(target), percent: 50.0, toward: None };
+        assert_eq!(state.apply_command(0, attack(1)), Err(CommandError::Teammate(1)));
+        assert!(state.attack_movements.is_empty());
+        assert_eq!(state.players.iter().find(|p| p.id == 0).unwrap().resources, resources);
+        // Nations off the team are fair game once they border
+        assert_eq!(state.apply_command(0, attack(2)), Err(CommandError::NotBordering(2)));
+    }
+}
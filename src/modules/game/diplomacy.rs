@@ -0,0 +1,283 @@
+use std::collections::BTreeSet;
+use serde::{Deserialize, Serialize};
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+use super::commands::CommandError;
+use super::state::GameState;
+
+// Proposals nobody answers are dropped after this many ticks
+pub const PROPOSAL_TICKS: u64 = 100;
+
+// Nations at peace cannot attack each other. Pacts run out after
+// `pact_duration` ticks, alliances hold until one side breaks them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Treaty {
+    Pact,
+    Alliance,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pact {
+    pub players: (usize, usize),
+    pub expires: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Proposal {
+    pub from: usize,
+    pub to: usize,
+    pub treaty: Treaty,
+    pub expires: u64,
+}
+
+// Broadcast to every client as they happen
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiplomacyEvent {
+    Proposed { from: usize, to: usize, treaty: Treaty },
+    Signed { players: (usize, usize), treaty: Treaty },
+    Broken { by: usize, with: usize, treaty: Treaty },
+    Expired { players: (usize, usize) },
+}
+
+// Treaties between nations, pairs are stored with the lower id first
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Diplomacy {
+    pub alliances: BTreeSet<(usize, usize)>,
+    pub pacts: Vec<Pact>,
+    pub proposals: Vec<Proposal>,
+    #[serde(skip)]
+    events: Vec<DiplomacyEvent>,
+}
+
+fn pair(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl Diplomacy {
+    pub fn treaty(&self, a: usize, b: usize) -> Option<Treaty> {
+        let players = pair(a, b);
+        if self.alliances.contains(&players) {
+            Some(Treaty::Alliance)
+        } else if self.pacts.iter().any(|pact| pact.players == players) {
+            Some(Treaty::Pact)
+        } else {
+            None
+        }
+    }
+
+    pub fn at_peace(&self, a: usize, b: usize) -> bool {
+        a != b && self.treaty(a, b).is_some()
+    }
+
+    pub fn allied(&self, a: usize, b: usize) -> bool {
+        self.alliances.contains(&pair(a, b))
+    }
+
+    pub fn proposal(&self, from: usize, to: usize) -> Option<&Proposal> {
+        self.proposals.iter().find(|p| p.from == from && p.to == to)
+    }
+
+    // Proposals waiting for `player` to answer
+    pub fn proposals_to(&self, player: usize) -> impl Iterator<Item = &Proposal> {
+        self.proposals.iter().filter(move |p| p.to == player)
+    }
+
+    fn sign(&mut self, a: usize, b: usize, treaty: Treaty, expires: u64) {
+        let players = pair(a, b);
+        self.pacts.retain(|pact| pact.players != players);
+        self.proposals.retain(|p| pair(p.from, p.to) != players);
+        match treaty {
+            Treaty::Alliance => {
+                self.alliances.insert(players);
+            }
+            Treaty::Pact => self.pacts.push(Pact { players, expires }),
+        }
+        self.events.push(DiplomacyEvent::Signed { players, treaty });
+    }
+}
+
+impl GameState {
+    // Offers a treaty, signed straight away when the other side already offered the same
+    pub fn propose_treaty(&mut self, from: usize, to: usize, treaty: Treaty) -> Result<(), CommandError> {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "propose_treaty");
+        if from == to {
+            return Err(CommandError::SelfTreaty);
+        }
+        if self.teammates(from, to) {
+            return Err(CommandError::Teammate(to));
+        }
+        if !self.players.iter().any(|p| p.id == to) {
+            return Err(CommandError::NotInGame(to));
+        }
+        if self.diplomacy.treaty(from, to).is_some_and(|current| current >= treaty) {
+            return Err(CommandError::AlreadyAtPeace(to));
+        }
+        if self.diplomacy.proposal(to, from).is_some_and(|p| p.treaty == treaty) {
+            return self.accept_treaty(from, to);
+        }
+
+        self.diplomacy.proposals.retain(|p| !(p.from == from && p.to == to));
+        let expires = self.tick + PROPOSAL_TICKS;
+        self.diplomacy.proposals.push(Proposal { from, to, treaty, expires });
+        self.diplomacy.events.push(DiplomacyEvent::Proposed { from, to, treaty });
+        Ok(())
+    }
+
+    // Signs the treaty `from` offered to `player`. Attacks between the two are
+    // called off and their investments returned.
+    pub fn accept_treaty(&mut self, player: usize, from: usize) -> Result<(), CommandError> {
+        let proposal = *self.diplomacy.proposal(from, player).ok_or(CommandError::NoProposal(from))?;
+        let expires = self.tick + self.config.pact_duration;
+        self.diplomacy.sign(from, player, proposal.treaty, expires);
+        crate::game_log!("Players {} and {} signed a {:?}", from, player, proposal.treaty);
+
+        let (between, others): (Vec<_>, Vec<_>) = std::mem::take(&mut self.attack_movements)
+            .into_iter()
+            .partition(|attack| pair(attack.source, attack.target) == pair(from, player));
+        self.attack_movements = others;
+        for attack in between {
+            if let Some(source) = self.players.iter_mut().find(|p| p.id == attack.source) {
+                source.resources += attack.investment.max(0);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn break_treaty(&mut self, player: usize, with: usize) -> Result<(), CommandError> {
+        let treaty = self.diplomacy.treaty(player, with).ok_or(CommandError::NoTreaty(with))?;
+        let players = pair(player, with);
+        self.diplomacy.alliances.remove(&players);
+        self.diplomacy.pacts.retain(|pact| pact.players != players);
+        self.diplomacy.events.push(DiplomacyEvent::Broken { by: player, with, treaty });
+        crate::game_log!("Player {} broke its {:?} with {}", player, treaty, with);
+        Ok(())
+    }
+
+    // Runs out pacts and proposals, and forgets the treaties of eliminated nations
+    pub(super) fn update_diplomacy(&mut self) {
+        let tick = self.tick;
+        let alive: BTreeSet<usize> = self.players.iter().map(|p| p.id).collect();
+        let diplomacy = &mut self.diplomacy;
+
+        diplomacy.alliances.retain(|(a, b)| alive.contains(a) && alive.contains(b));
+        diplomacy.proposals.retain(|p| p.expires > tick && alive.contains(&p.from) && alive.contains(&p.to));
+        let mut expired = Vec::new();
+        diplomacy.pacts.retain(|pact| {
+            let (a, b) = pact.players;
+            if pact.expires <= tick && alive.contains(&a) && alive.contains(&b) {
+                expired.push(pact.players);
+            }
+            pact.expires > tick && alive.contains(&a) && alive.contains(&b)
+        });
+        diplomacy.events.extend(expired.into_iter().map(|players| DiplomacyEvent::Expired { players }));
+    }
+
+    // Events since the last call, for the server to broadcast
+    pub fn take_diplomacy_events(&mut self) -> Vec<DiplomacyEvent> {
+        std::mem::take(&mut self.diplomacy.events)
+    }
+
+    // With `alliance_victory`, the game also ends once every nation left is allied
+    // with, or on the same team as, all the others
+    pub fn winning_alliance(&self) -> Option<Vec<usize>> {
+        let players: Vec<usize> = self.players.iter().map(|p| p.id).collect();
+        let all_allied = players.iter().enumerate().all(|(i, &a)| {
+            players[i + 1..].iter().all(|&b| self.diplomacy.allied(a, b) || self.teammates(a, b))
+        });
+        let won = self.config.alliance_victory && players.len() > 1 && all_allied;
+        won.then_some(players)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::types::Player;
+
+    fn three_nations() -> GameState {
+        let config = GameConfig { grid_width: 30, grid_height: 10, num_players: 3, seed: Some(1), ..GameConfig::default() };
+        let mut state = GameState::new(config.clone());
+        for id in 0..3 {
+            state.players.push(Player::new(id, id * 10 + 5, 5, &config));
+            for row in state.grid.iter_mut() {
+                for cell in &mut row[id * 10..id * 10 + 10] {
+                    *cell = Some(id);
+                }
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn treaties_block_attacks() {
+        let mut state = three_nations();
+        state.start_attack(1, 0, 100, None);
+        state.propose_treaty(0, 1, Treaty::Pact).unwrap();
+        assert_eq!(state.propose_treaty(0, 0, Treaty::Pact), Err(CommandError::SelfTreaty));
+        assert_eq!(state.accept_treaty(2, 0), Err(CommandError::NoProposal(0)));
+
+        let resources = state.players[1].resources;
+        state.accept_treaty(1, 0).unwrap();
+        assert!(state.diplomacy.at_peace(0, 1));
+        // The running attack is called off and refunded
+        assert!(state.attack_movements.is_empty());
+        assert_eq!(state.players[1].resources, resources + 100);
+
+        state.start_attack(0, 1, 100, None);
+        assert!(state.attack_movements.is_empty());
+        assert_eq!(state.propose_treaty(1, 0, Treaty::Pact), Err(CommandError::AlreadyAtPeace(0)));
+
+        state.break_treaty(1, 0).unwrap();
+        state.start_attack(0, 1, 100, None);
+        assert_eq!(state.attack_movements.len(), 1);
+        assert_eq!(state.take_diplomacy_events(), vec![
+            DiplomacyEvent::Proposed { from: 0, to: 1, treaty: Treaty::Pact },
+            DiplomacyEvent::Signed { players: (0, 1), treaty: Treaty::Pact },
+            DiplomacyEvent::Broken { by: 1, with: 0, treaty: Treaty::Pact },
+        ]);
+    }
+
+    #[test]
+    fn pacts_run_out_and_alliances_win() {
+        let mut state = three_nations();
+        state.config.alliance_victory = true;
+        // Crossed proposals sign the treaty
+        state.propose_treaty(0, 2, Treaty::Pact).unwrap();
+        state.propose_treaty(2, 0, Treaty::Pact).unwrap();
+        assert!(state.diplomacy.at_peace(0, 2));
+
+        state.tick += state.config.pact_duration;
+        state.update_diplomacy();
+        assert!(!state.diplomacy.at_peace(0, 2));
+        assert_eq!(state.take_diplomacy_events().last(), Some(&DiplomacyEvent::Expired { players: (0, 2) }));
+
+        for (a, b) in [(0, 1), (1, 2), (0, 2)] {
+            state.propose_treaty(a, b, Treaty::Alliance).unwrap();
+            assert_eq!(state.winning_alliance(), None);
+            state.accept_treaty(b, a).unwrap();
+        }
+        assert_eq!(state.winning_alliance(), Some(vec![0, 1, 2]));
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn teammates_hold_fire_and_win_together() {
+        let mut state = three_nations();
+        state.config.teams = vec![0, 0, 1];
+        for player in &mut state.players {
+            player.team = state.config.team_for(player.id);
+        }
+        state.start_attack(0, 1, 100, None);
+        assert!(state.attack_movements.is_empty());
+        assert_eq!(state.propose_treaty(1, 0, Treaty::Pact), Err(CommandError::Teammate(0)));
+        assert_eq!(state.winning_team(), None);
+
+        state.players.retain(|p| p.id != 2);
+        assert_eq!(state.winning_team(), Some(0));
+        assert_eq!(state.winner(), None);
+        assert!(state.is_finished());
+    }
+}
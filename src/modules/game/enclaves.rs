@@ -0,0 +1,217 @@
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+use super::state::GameState;
+
+// Visit marks of the enclave searches, kept from one tick to the next so the grid
+// sized buffer is neither allocated nor cleared every tick. Searches are numbered
+// on across ticks, marks up to `base` were left by earlier ones.
+#[derive(Clone, Default)]
+pub(crate) struct EnclaveMarks {
+    seen: Vec<u32>,
+    base: u32,
+}
+
+impl EnclaveMarks {
+    // This tick's search that last visited `cell`, 0 when none did
+    fn search(&self, cell: usize) -> usize {
+        self.seen[cell].saturating_sub(self.base) as usize
+    }
+}
+
+impl GameState {
+    // Hands pockets of empty or enemy land walled in by one nation over to it.
+    // Only pockets touching a cell captured this tick can have closed, and each
+    // search gives up past `max_enclave_size` cells, so the pass stays cheap on
+    // large grids. Pockets reaching water or the map edge are not enclosed.
+    pub(super) fn capture_enclaves(&mut self) {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "capture_enclaves");
+        if self.config.max_enclave_size == 0 || self.changed_cells.is_empty() {
+            return;
+        }
+        let (width, height) = (self.config.grid_width, self.config.grid_height);
+
+        // Every changed cell starts at most four searches, the marks start over
+        // before their numbers could run out
+        let mut marks = std::mem::take(&mut self.enclave_marks);
+        let most_searches = 4 * self.changed_cells.len() as u64 + 1;
+        if marks.seen.len() != width * height || marks.base as u64 + most_searches > u32::MAX as u64 {
+            marks = EnclaveMarks { seen: vec![0; width * height], base: 0 };
+        }
+
+        // `searches[i]` is the owner and outcome of this tick's search i, numbered
+        // from 1. A search that runs into cells of an earlier one for the same owner
+        // that got out is not enclosed either.
+        let mut searches: Vec<(usize, bool)> = vec![(usize::MAX, true)];
+        let mut region = Vec::new();
+        let mut captured = Vec::new();
+
+        let frontier: Vec<(usize, usize)> = self.changed_cells.iter().map(|&(x, y, _)| (x, y)).collect();
+        for (x, y) in frontier {
+            let Some(owner) = self.grid[y][x] else {
+                continue;
+            };
+            for (sx, sy) in neighbors(x, y, width, height) {
+                if !self.terrain.is_passable(sx, sy) || self.grid[sy][sx] == Some(owner) {
+                    continue;
+                }
+                let earlier = searches[marks.search(sy * width + sx)];
+                if earlier.0 == owner && earlier.1 {
+                    continue;
+                }
+
+                let id = marks.base + searches.len() as u32;
+                let escaped = self.fill_pocket(sx, sy, owner, id, &mut marks, &searches, &mut region);
+                searches.push((owner, escaped));
+                if !escaped {
+                    for &(cx, cy) in &region {
+                        captured.push((cx, cy, self.grid[cy][cx]));
+                        self.grid[cy][cx] = Some(owner);
+                    }
+                    crate::game_log!("Player {} encircled {} cells around ({}, {})", owner, region.len(), sx, sy);
+                }
+            }
+        }
+        marks.base += searches.len() as u32 - 1;
+        self.enclave_marks = marks;
+        self.changed_cells.extend(captured);
+    }
+
+    // Collects the region of land not owned by `owner` around (x, y) into `region`,
+    // returns true when it is open: too large, touching water or the map edge, or
+    // joined to a region already known to be open
+    #[allow(clippy::too_many_arguments)]
+    fn fill_pocket(
+        &self,
+        x: usize,
+        y: usize,
+        owner: usize,
+        id: u32,
+        marks: &mut EnclaveMarks,
+        searches: &[(usize, bool)],
+        region: &mut Vec<(usize, usize)>,
+    ) -> bool {
+        let (width, height) = (self.config.grid_width, self.config.grid_height);
+        region.clear();
+        region.push((x, y));
+        marks.seen[y * width + x] = id;
+
+        let mut next = 0;
+        while next < region.len() {
+            let (cx, cy) = region[next];
+            next += 1;
+            if cx == 0 || cy == 0 || cx == width - 1 || cy == height - 1 {
+                return true;
+            }
+            for (nx, ny) in neighbors(cx, cy, width, height) {
+                let cell = ny * width + nx;
+                if marks.seen[cell] == id || self.grid[ny][nx] == Some(owner) {
+                    continue;
+                }
+                if !self.terrain.is_passable(nx, ny) {
+                    return true;
+                }
+                // Teammates, allies and pact partners are never swallowed
+                if self.grid[ny][nx].is_some_and(|other| self.is_friendly(owner, other)) {
+                    return true;
+                }
+                let earlier = searches[marks.search(cell)];
+                if earlier.0 == owner && earlier.1 {
+                    return true;
+                }
+                marks.seen[cell] = id;
+                region.push((nx, ny));
+                if region.len() > self.config.max_enclave_size {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+fn neighbors(x: usize, y: usize, width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)]
+        .into_iter()
+        .filter(move |&(nx, ny)| nx < width && ny < height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::terrain::Terrain;
+
+    // Player 0 draws a ring from (10, 5) to (20, 15) on an empty 40x20 grid, with
+    // player 1 holding a cell inside it
+    fn ring(gap: bool) -> GameState {
+        let config = GameConfig { grid_width: 40, grid_height: 20, num_players: 2, seed: Some(1), ..GameConfig::default() };
+        let mut state = GameState::new(config);
+        for y in 5..=15 {
+            for x in 10..=20 {
+                if x == 10 || x == 20 || y == 5 || y == 15 {
+                    state.grid[y][x] = Some(0);
+                }
+            }
+        }
+        state.grid[10][15] = Some(1);
+        if gap {
+            state.grid[15][12] = None;
+        }
+        state.changed_cells.push((20, 10, None));
+        state
+    }
+
+    #[test]
+    fn encircled_pockets_change_hands() {
+        let mut state = ring(false);
+        state.capture_enclaves();
+        for y in 6..15 {
+            for x in 11..20 {
+                assert_eq!(state.grid[y][x], Some(0), "({}, {}) was not captured", x, y);
+            }
+        }
+        assert!(state.changed_cells.contains(&(15, 10, Some(1))));
+        // Outside the ring nothing moves
+        assert_eq!(state.grid[10][25], None);
+        assert_eq!(state.grid[10][5], None);
+    }
+
+    #[test]
+    fn open_or_large_pockets_stay() {
+        // Land along a lake shore is not enclosed by the nation alone
+        let mut state = ring(false);
+        state.terrain.set(15, 12, Terrain::Water);
+        state.capture_enclaves();
+        assert_eq!(state.grid[10][15], Some(1));
+
+        let mut state = ring(true);
+        state.capture_enclaves();
+        assert_eq!(state.grid[10][15], Some(1));
+        assert_eq!(state.grid[10][11], None);
+
+        // The 81 cells inside are over the limit
+        let mut state = ring(false);
+        state.config.max_enclave_size = 80;
+        state.capture_enclaves();
+        assert_eq!(state.grid[10][15], Some(1));
+    }
+
+    #[test]
+    fn marks_of_earlier_ticks_are_ignored() {
+        for base in [None, Some(u32::MAX - 2)] {
+            let mut state = ring(true);
+            state.capture_enclaves();
+            assert_eq!(state.grid[10][15], Some(1));
+            if let Some(base) = base {
+                state.enclave_marks.base = base;
+            }
+
+            // Closing the gap a tick later encircles the pocket found open before
+            state.grid[15][12] = Some(0);
+            state.changed_cells = vec![(12, 15, None)];
+            state.capture_enclaves();
+            assert_eq!(state.grid[10][15], Some(0));
+            assert_eq!(state.grid[10][11], Some(0));
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use rand::Rng;
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+use super::state::GameState;
+
+// A horizontal run of cells owned by one nation, `end` is exclusive
+struct Run {
+    y: usize,
+    start: usize,
+    end: usize,
+    owner: usize,
+}
+
+impl GameState {
+    // With fragment decay on, finds the pieces of every nation cut off from its
+    // core, counts them in `Player::cut_off` so they earn nothing, and lets the
+    // cells on their edges go neutral one by one
+    pub(super) fn decay_fragments(&mut self) {
+        if !self.config.fragment_decay {
+            return;
+        }
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "decay_fragments");
+        let (width, height) = (self.config.grid_width, self.config.grid_height);
+        let (runs, mut pieces) = self.territory_runs();
+        let roots: Vec<usize> = (0..runs.len()).map(|i| pieces.find(i)).collect();
+        let mut sizes = vec![0; runs.len()];
+        for (run, &root) in runs.iter().zip(&roots) {
+            sizes[root] += run.end - run.start;
+        }
+
+        // The core is the piece holding the capital, or the largest one once the
+        // capital has fallen
+        let mut core: Vec<Option<usize>> = vec![None; self.config.num_players];
+        for player in &self.players {
+            core[player.id] = runs.iter()
+                .position(|run| run.y == player.y && (run.start..run.end).contains(&player.x) && run.owner == player.id)
+                .map(|i| roots[i]);
+        }
+        let has_capital: Vec<bool> = core.iter().map(Option::is_some).collect();
+        for (i, run) in runs.iter().enumerate() {
+            let root = roots[i];
+            if has_capital[run.owner] || root != i {
+                continue;
+            }
+            if core[run.owner].is_none_or(|best| sizes[root] > sizes[best]) {
+                core[run.owner] = Some(root);
+            }
+        }
+
+        let mut cut_off = vec![0; self.config.num_players];
+        let mut crumbled = Vec::new();
+        for (run, &root) in runs.iter().zip(&roots) {
+            if core[run.owner] == Some(root) {
+                continue;
+            }
+            let (y, owner) = (run.y, run.owner);
+            for x in run.start..run.end {
+                let on_edge = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)]
+                    .into_iter()
+                    .any(|(nx, ny)| nx >= width || ny >= height || self.grid[ny][nx] != Some(owner));
+                if on_edge && self.rng.gen_bool(self.config.fragment_decay_chance) {
+                    crumbled.push((x, y));
+                } else {
+                    cut_off[owner] += 1;
+                }
+            }
+        }
+        // Applied afterwards so a fragment only loses its outer layer per tick
+        for (x, y) in crumbled {
+            self.changed_cells.push((x, y, self.grid[y][x]));
+            self.grid[y][x] = None;
+        }
+        for player in &mut self.players {
+            player.cut_off = cut_off[player.id];
+        }
+    }
+
+    // Splits every row into runs of one owner and joins runs that touch runs of
+    // the same owner in the row above, so each set is one connected piece
+    fn territory_runs(&self) -> (Vec<Run>, DisjointSets) {
+        let mut runs: Vec<Run> = Vec::new();
+        let mut pieces = DisjointSets::default();
+        let mut previous_row = 0..0;
+
+        for (y, row) in self.grid.iter().enumerate() {
+            let row_start = runs.len();
+            let mut x = 0;
+            while x < row.len() {
+                let Some(owner) = row[x] else {
+                    x += 1;
+                    continue;
+                };
+                let start = x;
+                while x < row.len() && row[x] == Some(owner) {
+                    x += 1;
+                }
+                runs.push(Run { y, start, end: x, owner });
+                pieces.add();
+            }
+
+            // Both rows are sorted by x, so overlaps are found walking them together
+            let (mut above, mut below) = (previous_row.start, row_start);
+            while above < previous_row.end && below < runs.len() {
+                let (a, b) = (&runs[above], &runs[below]);
+                if a.start < b.end && b.start < a.end && a.owner == b.owner {
+                    pieces.union(above, below);
+                }
+                if a.end < b.end {
+                    above += 1;
+                } else {
+                    below += 1;
+                }
+            }
+            previous_row = row_start..runs.len();
+        }
+        (runs, pieces)
+    }
+}
+
+// Union-find over run indices
+#[derive(Default)]
+struct DisjointSets {
+    parent: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn add(&mut self) {
+        self.parent.push(self.parent.len());
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    // The lower index becomes the root, which keeps roots in scan order
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::types::Player;
+
+    // Player 0 has its capital block at (2..8, 2..8) and a cut-off 4x4 block at
+    // (20..24, 10..14), player 1 lost its capital and owns two strips
+    fn split_nations(chance: f64) -> GameState {
+        let config = GameConfig {
+            grid_width: 40,
+            grid_height: 20,
+            num_players: 2,
+            seed: Some(2),
+            fragment_decay: true,
+            fragment_decay_chance: chance,
+            ..GameConfig::default()
+        };
+        let mut state = GameState::new(config.clone());
+        state.players.push(Player::new(0, 4, 4, &config));
+        state.players.push(Player::new(1, 35, 2, &config));
+        for y in 0..20 {
+            for x in 0..40 {
+                state.grid[y][x] = match (x, y) {
+                    (2..=7, 2..=7) | (20..=23, 10..=13) => Some(0),
+                    (30..=31, 5..=17) | (37, 5..=9) => Some(1),
+                    _ => None,
+                };
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn fragments_earn_nothing() {
+        let mut state = split_nations(0.0);
+        state.decay_fragments();
+        assert_eq!(state.players[0].cut_off, 16);
+        // Without its capital the larger strip is the core
+        assert_eq!(state.players[1].cut_off, 5);
+
+        let player = &mut state.players[0];
+        player.area = 52;
+        player.resources = 0;
+        player.update_resources(&state.config);
+        assert_eq!(player.resources, 36);
+    }
+
+    #[test]
+    fn fragments_crumble_from_their_edges() {
+        let mut state = split_nations(1.0);
+        state.decay_fragments();
+        // The 12 edge cells of the block go, its 2x2 middle is left
+        assert_eq!(state.players[0].cut_off, 4);
+        assert_eq!(state.grid[10][20], None);
+        assert_eq!(state.grid[11][21], Some(0));
+        assert!(state.changed_cells.contains(&(20, 10, Some(0))));
+        // The core is untouched
+        assert!((2..8).all(|x| state.grid[2][x] == Some(0)));
+
+        state.decay_fragments();
+        assert_eq!(state.players[0].cut_off, 0);
+        assert_eq!(state.grid[11][21], None);
+    }
+}
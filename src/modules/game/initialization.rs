@@ -1,37 +1,76 @@
 use rand::Rng;
-use crate::modules::config::*;
+use crate::modules::config::{GameConfig, PLACEMENT_RADIUS};
+use crate::modules::map::{GameMap, MapError};
+use crate::modules::mapgen;
 use crate::modules::types::Player;
 use super::state::GameState;
 
 impl GameState {
+    // Starts from the map named in the config, a generated one, or a blank grid
+    pub fn from_config(config: GameConfig) -> Result<Self, MapError> {
+        if let Some(name) = config.map.clone() {
+            return Self::with_map(config, GameMap::load(&name)?);
+        }
+        if config.generate_map {
+            // The map is derived from the game seed, so it has to be known up front
+            let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+            let config = GameConfig { seed: Some(seed), ..config };
+            let map = mapgen::generate(&config, seed)?;
+            return Self::with_map(config, map);
+        }
+        Ok(Self::new(config))
+    }
+
+    // The grid takes the size of the map, whose spawn points replace section placement.
+    // Maps without any are placed by sections like blank grids, which must fit every nation.
+    pub fn with_map(mut config: GameConfig, map: GameMap) -> Result<Self, MapError> {
+        config.grid_width = map.terrain.width();
+        config.grid_height = map.terrain.height();
+        config.validate_settings().map_err(|e| MapError::Invalid(e.to_string()))?;
+        if map.spawns.is_empty() {
+            config.check_placement().map_err(|e| MapError::Invalid(e.to_string()))?;
+        } else if map.spawns.len() < config.num_players {
+            return Err(MapError::Invalid(format!(
+                "{} players do not fit on {} spawn points", config.num_players, map.spawns.len()
+            )));
+        }
+
+        let mut state = Self::new(config);
+        state.terrain = map.terrain;
+        state.spawn_points = map.spawns;
+        Ok(state)
+    }
+
     pub fn initialize_players(&mut self) {
-        println!("Initializing {} players on {}x{} grid...", NUM_PLAYERS, GRID_WIDTH, GRID_HEIGHT);
-        let mut rng = rand::thread_rng();
+        if !self.spawn_points.is_empty() {
+            self.initialize_players_on_spawns();
+            return;
+        }
+
+        let (grid_width, grid_height) = (self.config.grid_width, self.config.grid_height);
+        crate::game_log!("Initializing {} players on {}x{} grid...", self.config.num_players, grid_width, grid_height);
         
         // Calculate optimal placement parameters for larger grid
-        let placement_radius = 3_usize;
-        let min_distance = (GRID_WIDTH * GRID_HEIGHT / NUM_PLAYERS) as f64;
-        let spacing = (min_distance.sqrt() as usize).max(placement_radius * 2);
+        let placement_radius = PLACEMENT_RADIUS;
+        let (spacing, sections_x, sections_y) = self.config.placement_sections();
         
-        println!("Grid spacing: {}, placement radius: {}", spacing, placement_radius);
+        crate::game_log!("Grid spacing: {}, placement radius: {}", spacing, placement_radius);
         
         // Pre-calculate grid sections for better distribution
-        let sections_x = GRID_WIDTH / spacing;
-        let sections_y = GRID_HEIGHT / spacing;
         let mut available_sections: Vec<(usize, usize)> = (0..sections_x)
             .flat_map(|x| (0..sections_y).map(move |y| (x, y)))
             .collect();
         
-        println!("Created {}x{} grid sections ({} total)", sections_x, sections_y, available_sections.len());
+        crate::game_log!("Created {}x{} grid sections ({} total)", sections_x, sections_y, available_sections.len());
         
-        for id in 0..NUM_PLAYERS {
+        for id in 0..self.config.num_players {
             if available_sections.is_empty() {
-                println!("Warning: No more sections available for player {}", id);
+                crate::game_log!("Warning: No more sections available for player {}", id);
                 continue;
             }
             
             // Select a random available section
-            let section_idx = rng.gen_range(0..available_sections.len());
+            let section_idx = self.rng.gen_range(0..available_sections.len());
             let (section_x, section_y) = available_sections.swap_remove(section_idx);
             
             // Calculate position within section
@@ -45,16 +84,16 @@ impl GameState {
             let max_attempts = 10;
             
             while !found_position && attempts < max_attempts {
-                let x = (base_x + placement_radius + rng.gen_range(0..offset_range))
-                    .min(GRID_WIDTH - placement_radius - 1);
-                let y = (base_y + placement_radius + rng.gen_range(0..offset_range))
-                    .min(GRID_HEIGHT - placement_radius - 1);
+                let x = (base_x + placement_radius + self.rng.gen_range(0..offset_range))
+                    .min(grid_width - placement_radius - 1);
+                let y = (base_y + placement_radius + self.rng.gen_range(0..offset_range))
+                    .min(grid_height - placement_radius - 1);
                 
                 if self.is_position_available(x as i32, y as i32, placement_radius as i32) {
-                    println!("Placing player {} in section ({}, {}) at position ({}, {})", 
+                    crate::game_log!("Placing player {} in section ({}, {}) at position ({}, {})", 
                         id, section_x, section_y, x, y);
                     
-                    let player = Player::new(id, x, y);
+                    let player = Player::new(id, x, y, &self.config);
                     self.players.push(player);
                     
                     // Create initial territory efficiently
@@ -66,30 +105,43 @@ impl GameState {
             }
             
             if !found_position {
-                println!("Warning: Could not find valid position for player {} in section ({}, {})", 
+                crate::game_log!("Warning: Could not find valid position for player {} in section ({}, {})", 
                     id, section_x, section_y);
             }
         }
         
-        println!("Successfully initialized {} players", self.players.len());
+        crate::game_log!("Successfully initialized {} players", self.players.len());
     }
 
-    fn create_initial_territory(&mut self, x: usize, y: usize, id: usize) {
+    // Picks a random spawn point for every nation when the map has more than needed
+    fn initialize_players_on_spawns(&mut self) {
+        crate::game_log!("Placing {} players on {} map spawn points...", self.config.num_players, self.spawn_points.len());
+        let mut available = self.spawn_points.clone();
+        for id in 0..self.config.num_players {
+            let (x, y) = available.swap_remove(self.rng.gen_range(0..available.len()));
+            self.players.push(Player::new(id, x, y, &self.config));
+            self.create_initial_territory(x, y, id);
+        }
+        crate::game_log!("Successfully initialized {} players", self.players.len());
+    }
+
+    pub(super) fn create_initial_territory(&mut self, x: usize, y: usize, id: usize) {
         // Pre-calculate bounds for better performance
         let min_x = x.saturating_sub(1);
-        let max_x = (x + 1).min(GRID_WIDTH - 1);
+        let max_x = (x + 1).min(self.config.grid_width - 1);
         let min_y = y.saturating_sub(1);
-        let max_y = (y + 1).min(GRID_HEIGHT - 1);
+        let max_y = (y + 1).min(self.config.grid_height - 1);
         
-        // Batch update territory
-        for y in min_y..=max_y {
-            let row = &mut self.grid[y];
-            for x in min_x..=max_x {
-                row[x] = Some(id);
+        // Batch update territory, leaving out water
+        for (y, row) in self.grid.iter_mut().enumerate().take(max_y + 1).skip(min_y) {
+            for (x, cell) in row.iter_mut().enumerate().take(max_x + 1).skip(min_x) {
+                if self.terrain.is_passable(x, y) {
+                    *cell = Some(id);
+                }
             }
         }
         
-        println!("Created initial territory for player {} at ({}, {}) with bounds: x={}..{}, y={}..{}", 
+        crate::game_log!("Created initial territory for player {} at ({}, {}) with bounds: x={}..{}, y={}..{}", 
             id, x, y, min_x, max_x, min_y, max_y);
     }
 }
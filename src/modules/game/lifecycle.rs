@@ -0,0 +1,301 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::modules::map::MapError;
+use crate::modules::timing::ExecutionTimer;
+use crate::modules::types::PlayerKind;
+use crate::TIMING_STATS;
+use super::state::GameState;
+
+// Where a server game is in its life. `remaining` counts server ticks left in
+// the phase. Games made with GameState::new run straight away, the server opens
+// a lobby for them with `open_lobby`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum Phase {
+    Lobby { remaining: u64 },
+    Countdown { remaining: u64 },
+    #[default]
+    Running,
+    // `remaining` is None when no new game follows
+    Finished { remaining: Option<u64> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VictoryReason {
+    LastSurvivor,
+    Team,
+    Alliance,
+    LandShare,
+    TimeLimit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Standing {
+    pub player: usize,
+    pub area: i32,
+    pub team: Option<usize>,
+    // Tick the nation was wiped out, None for nations still standing
+    pub eliminated: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameResult {
+    pub tick: u64,
+    pub reason: VictoryReason,
+    pub winners: Vec<usize>,
+    // Nations still standing by area, then the eliminated ones, last to fall first
+    pub standings: Vec<Standing>,
+}
+
+// Broadcast to every client as they happen
+#[derive(Clone, Debug, PartialEq)]
+pub enum LifecycleEvent {
+    PhaseChanged(Phase),
+    GameOver(GameResult),
+    // A new game replaced the finished one, clients need its terrain and teams
+    NewGame,
+}
+
+impl GameState {
+    // Puts a fresh game in the lobby, or straight into the countdown without one
+    pub fn open_lobby(&mut self) {
+        let phase = if self.config.lobby_ticks > 0 {
+            Phase::Lobby { remaining: self.config.lobby_ticks }
+        } else {
+            self.countdown()
+        };
+        self.set_phase(phase);
+    }
+
+    // Advances the lifecycle by one server tick: waits out the lobby and the
+    // countdown, runs the game and ends it once someone has won, then sets up the
+    // next game after the pause
+    pub fn step(&mut self) -> Result<(), MapError> {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "lifecycle_step");
+        match self.phase {
+            Phase::Lobby { remaining: 0 } => {
+                let countdown = self.countdown();
+                self.set_phase(countdown);
+            }
+            Phase::Countdown { remaining: 0 } => self.set_phase(Phase::Running),
+            Phase::Lobby { remaining } => self.phase = Phase::Lobby { remaining: remaining - 1 },
+            Phase::Countdown { remaining } => self.phase = Phase::Countdown { remaining: remaining - 1 },
+            Phase::Running => {
+                self.update();
+                if let Some((reason, winners)) = self.victory() {
+                    self.finish(reason, winners);
+                }
+            }
+            Phase::Finished { remaining: Some(0) } => self.restart()?,
+            Phase::Finished { remaining: Some(remaining) } => {
+                self.phase = Phase::Finished { remaining: Some(remaining - 1) };
+            }
+            Phase::Finished { remaining: None } => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.phase == Phase::Running
+    }
+
+    // Whether the game is won and by whom. Being the last nation or team standing
+    // always ends it, holding `victory_land_share` of the land and reaching
+    // `time_limit_ticks` only when they are configured.
+    pub fn victory(&self) -> Option<(VictoryReason, Vec<usize>)> {
+        // When the last nations fall on the same tick, the one ranked first among
+        // them in the standings wins
+        if self.players.len() <= 1 {
+            return Some((VictoryReason::LastSurvivor, self.standings().iter().take(1).map(|s| s.player).collect()));
+        }
+        if self.winning_team().is_some() {
+            return Some((VictoryReason::Team, self.players.iter().map(|p| p.id).collect()));
+        }
+        if let Some(players) = self.winning_alliance() {
+            return Some((VictoryReason::Alliance, players));
+        }
+
+        let largest = self.players.iter()
+            .max_by_key(|p| (p.area, std::cmp::Reverse(p.id)))
+            .map(|p| (p.id, p.area));
+        if self.config.victory_land_share > 0.0 {
+            let land = self.terrain.passable_cells();
+            if let Some((id, area)) = largest.filter(|&(_, area)| area as f64 >= land as f64 * self.config.victory_land_share) {
+                crate::game_log!("Player {} holds {} of {} land cells", id, area, land);
+                return Some((VictoryReason::LandShare, vec![id]));
+            }
+        }
+        if self.config.time_limit_ticks > 0 && self.tick >= self.config.time_limit_ticks {
+            return largest.map(|(id, _)| (VictoryReason::TimeLimit, vec![id]));
+        }
+        None
+    }
+
+    // Nations still standing by area, then the eliminated ones, last to fall first
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = self.players.iter()
+            .map(|p| Standing { player: p.id, area: p.area, team: p.team, eliminated: None })
+            .collect();
+        standings.sort_by(|a, b| b.area.cmp(&a.area).then(a.player.cmp(&b.player)));
+        standings.extend(self.eliminations.iter().rev().map(|e| Standing {
+            player: e.player,
+            area: 0,
+            team: self.config.team_for(e.player),
+            eliminated: Some(e.tick),
+        }));
+        standings
+    }
+
+    // Events since the last call, for the server to broadcast
+    pub fn take_lifecycle_events(&mut self) -> Vec<LifecycleEvent> {
+        std::mem::take(&mut self.lifecycle_events)
+    }
+
+    fn countdown(&self) -> Phase {
+        if self.config.countdown_ticks > 0 {
+            Phase::Countdown { remaining: self.config.countdown_ticks }
+        } else {
+            Phase::Running
+        }
+    }
+
+    fn set_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.lifecycle_events.push(LifecycleEvent::PhaseChanged(phase));
+    }
+
+    fn finish(&mut self, reason: VictoryReason, winners: Vec<usize>) {
+        crate::game_log!("Game over at tick {} ({:?}), won by {:?}", self.tick, reason, winners);
+        let result = GameResult { tick: self.tick, reason, winners, standings: self.standings() };
+        self.result = Some(result.clone());
+        self.lifecycle_events.push(LifecycleEvent::GameOver(result));
+        let remaining = (self.config.restart_ticks > 0).then_some(self.config.restart_ticks);
+        self.set_phase(Phase::Finished { remaining });
+    }
+
+    // Replaces the finished game with a new one on the same configuration. Its
+    // seed is drawn from this game's, so a server started with a fixed seed
+    // plays the same sequence of games. Claims carry over to the new nations.
+    fn restart(&mut self) -> Result<(), MapError> {
+        let mut config = self.config.clone();
+        config.seed = Some(self.rng.gen());
+        let mut next = GameState::from_config(config)?;
+        next.initialize_players();
+        if self.recording.is_some() {
+            next.start_recording();
+        }
+        next.human_players = std::mem::take(&mut self.human_players);
+        next.human_players.retain(|&id| next.players.iter().any(|p| p.id == id));
+        // Players keep their name and colour from one game to the next
+        let humans: Vec<_> = self.players.iter().filter(|p| next.human_players.contains(&p.id)).collect();
+        for player in humans {
+            let _ = next.set_identity(player.id, &player.name, &player.color);
+            next.set_kind(player.id, PlayerKind::Human);
+        }
+        crate::game_log!("Starting a new game with seed {}", next.seed);
+
+        *self = next;
+        self.lifecycle_events.push(LifecycleEvent::NewGame);
+        self.open_lobby();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::terrain::Terrain;
+    use crate::modules::types::Player;
+
+    fn lifecycle_config() -> GameConfig {
+        GameConfig {
+            grid_width: 40,
+            grid_height: 30,
+            num_players: 2,
+            seed: Some(4),
+            lobby_ticks: 2,
+            countdown_ticks: 1,
+            restart_ticks: 2,
+            time_limit_ticks: 3,
+            ..GameConfig::default()
+        }
+    }
+
+    #[test]
+    fn games_run_through_their_phases_and_restart() {
+        let mut state = GameState::new(lifecycle_config());
+        state.initialize_players();
+        state.open_lobby();
+        let mut phases = vec![state.phase];
+        while state.tick < 3 {
+            state.step().unwrap();
+            if phases.last() != Some(&state.phase) {
+                phases.push(state.phase);
+            }
+        }
+        assert_eq!(phases, vec![
+            Phase::Lobby { remaining: 2 },
+            Phase::Lobby { remaining: 1 },
+            Phase::Lobby { remaining: 0 },
+            Phase::Countdown { remaining: 1 },
+            Phase::Countdown { remaining: 0 },
+            Phase::Running,
+            Phase::Finished { remaining: Some(2) },
+        ]);
+
+        let result = state.result.clone().expect("the game has a result");
+        assert_eq!(result.reason, VictoryReason::TimeLimit);
+        assert_eq!(result.winners, vec![result.standings[0].player]);
+        let events = state.take_lifecycle_events();
+        assert!(events.contains(&LifecycleEvent::GameOver(result)));
+
+        // The pause runs out and the next game opens its lobby
+        let seed = state.seed;
+        for _ in 0..3 {
+            state.step().unwrap();
+        }
+        assert_eq!(state.tick, 0);
+        assert_ne!(state.seed, seed);
+        assert_eq!(state.result, None);
+        assert_eq!(state.phase, Phase::Lobby { remaining: 2 });
+        assert_eq!(state.players.len(), 2);
+        assert_eq!(state.take_lifecycle_events(), vec![
+            LifecycleEvent::NewGame,
+            LifecycleEvent::PhaseChanged(Phase::Lobby { remaining: 2 }),
+        ]);
+    }
+
+    #[test]
+    fn land_share_and_last_survivor_decide_the_winner() {
+        let config = GameConfig { victory_land_share: 0.5, ..lifecycle_config() };
+        let mut state = GameState::new(config.clone());
+        // Half of the 1200 cells are water, so 300 land cells win
+        for y in 0..15 {
+            for x in 0..40 {
+                state.terrain.set(x, y, Terrain::Water);
+            }
+        }
+        state.players.push(Player::new(0, 5, 20, &config));
+        state.players.push(Player::new(1, 30, 20, &config));
+        state.players[0].area = 299;
+        assert_eq!(state.victory(), None);
+        state.players[0].area = 300;
+        assert_eq!(state.victory(), Some((VictoryReason::LandShare, vec![0])));
+
+        state.players.pop();
+        state.eliminations.push(crate::modules::types::Elimination { player: 1, tick: 2 });
+        assert_eq!(state.victory(), Some((VictoryReason::LastSurvivor, vec![0])));
+        let standings: Vec<_> = state.standings().iter().map(|s| (s.player, s.eliminated)).collect();
+        assert_eq!(standings, vec![(0, None), (1, Some(2))]);
+
+        // Nobody is left when the last two go down on the same tick
+        state.players.clear();
+        state.eliminations = vec![
+            crate::modules::types::Elimination { player: 1, tick: 2 },
+            crate::modules::types::Elimination { player: 0, tick: 2 },
+        ];
+        assert_eq!(state.victory(), Some((VictoryReason::LastSurvivor, vec![0])));
+    }
+}
@@ -2,5 +2,25 @@ mod state;
 mod initialization;
 mod update;
 mod territory;
+mod snapshot;
+mod replay;
+mod commands;
+mod naval;
+mod enclaves;
+mod fragments;
+mod diplomacy;
+mod lifecycle;
+mod roster;
+mod stats;
+#[cfg(test)]
+pub(crate) mod testing;
 
 pub use state::GameState;
+pub use snapshot::{SnapshotError, SNAPSHOT_VERSION};
+pub use replay::{AttackRecord, ReplayError, ReplayLog, ReplayPlayer, REPLAY_VERSION};
+pub use commands::{CommandError, CommandRecord, PlayerCommand};
+pub use naval::{Landing, SeaRoutes};
+pub use diplomacy::{Diplomacy, DiplomacyEvent, Pact, Proposal, Treaty, PROPOSAL_TICKS};
+pub use lifecycle::{GameResult, LifecycleEvent, Phase, Standing, VictoryReason};
+pub use roster::LeaderboardEntry;
+pub use stats::{PlayerStats, StatsHistory, StatsTracker, STATS_HISTORY};
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use crate::modules::timing::ExecutionTimer;
+use crate::modules::types::AttackMovement;
+use crate::TIMING_STATS;
+use super::state::GameState;
+
+const UNREACHED: u32 = u32::MAX;
+
+// A coastal cell a nation can land on, `owner` is None for empty land and
+// `distance` the number of water cells crossed to get there
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Landing {
+    pub x: usize,
+    pub y: usize,
+    pub owner: Option<usize>,
+    pub distance: usize,
+}
+
+// Shortest routes across water from one nation's coast, found by a breadth-first
+// search started from every water cell touching its territory
+pub struct SeaRoutes {
+    width: usize,
+    height: usize,
+    distance: Vec<u32>,
+    parent: Vec<u32>,
+    landings: Vec<Landing>,
+}
+
+impl SeaRoutes {
+    // Every shore cell outside the nation reachable by boat, in row-major order
+    pub fn landings(&self) -> &[Landing] {
+        &self.landings
+    }
+
+    pub fn landing(&self, x: usize, y: usize) -> Option<&Landing> {
+        self.landings.iter().find(|landing| (landing.x, landing.y) == (x, y))
+    }
+
+    // Water cells from the launching coast to the one next to the landing
+    pub fn route_to(&self, x: usize, y: usize) -> Option<Vec<(usize, usize)>> {
+        let last = self.neighbors(x, y)
+            .filter(|&i| self.distance[i] != UNREACHED)
+            .min_by_key(|&i| (self.distance[i], i))?;
+
+        let mut route = vec![last];
+        let mut current = last;
+        while self.parent[current] != current as u32 {
+            current = self.parent[current] as usize;
+            route.push(current);
+        }
+        route.reverse();
+        Some(route.into_iter().map(|i| (i % self.width, i / self.width)).collect())
+    }
+
+    fn neighbors(&self, x: usize, y: usize) -> impl Iterator<Item = usize> {
+        let (width, height) = (self.width, self.height);
+        [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)]
+            .into_iter()
+            .filter(move |&(nx, ny)| nx < width && ny < height)
+            .map(move |(nx, ny)| ny * width + nx)
+    }
+}
+
+impl GameState {
+    pub fn sea_routes(&self, player_id: usize) -> SeaRoutes {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "sea_routes");
+        let (width, height) = (self.config.grid_width, self.config.grid_height);
+        let mut routes = SeaRoutes {
+            width,
+            height,
+            distance: vec![UNREACHED; width * height],
+            parent: vec![UNREACHED; width * height],
+            landings: Vec::new(),
+        };
+
+        // Boats set out from any water cell on the nation's coast
+        let mut queue = VecDeque::new();
+        for y in 0..height {
+            for x in 0..width {
+                if self.terrain.is_passable(x, y) {
+                    continue;
+                }
+                if routes.neighbors(x, y).any(|i| self.grid[i / width][i % width] == Some(player_id)) {
+                    let i = y * width + x;
+                    routes.distance[i] = 0;
+                    routes.parent[i] = i as u32;
+                    queue.push_back(i);
+                }
+            }
+        }
+
+        while let Some(current) = queue.pop_front() {
+            let (x, y) = (current % width, current / width);
+            let next: Vec<usize> = routes.neighbors(x, y).collect();
+            for i in next {
+                if routes.distance[i] == UNREACHED && !self.terrain.is_passable(i % width, i / width) {
+                    routes.distance[i] = routes.distance[current] + 1;
+                    routes.parent[i] = current as u32;
+                    queue.push_back(i);
+                }
+            }
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                let owner = self.grid[y][x];
+                if !self.terrain.is_passable(x, y) || owner == Some(player_id) {
+                    continue;
+                }
+                let distance = routes.neighbors(x, y)
+                    .map(|i| routes.distance[i])
+                    .filter(|&d| d != UNREACHED)
+                    .min();
+                if let Some(distance) = distance {
+                    routes.landings.push(Landing { x, y, owner, distance: distance as usize + 1 });
+                }
+            }
+        }
+        routes
+    }
+
+    // Sends `investment` by boat to the coastal cell (x, y), returns false when no
+    // route leads there. The boat attacks whoever owns the landing cell at launch.
+    pub fn start_naval_attack(&mut self, source: usize, x: usize, y: usize, investment: i32) -> bool {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "start_naval_attack");
+        let routes = self.sea_routes(source);
+        let (Some(landing), Some(route)) = (routes.landing(x, y).copied(), routes.route_to(x, y)) else {
+            return false;
+        };
+        if landing.owner.is_some_and(|owner| self.is_friendly(source, owner)) {
+            return false;
+        }
+        let Some(player) = self.players.iter_mut().find(|p| p.id == source) else {
+            return false;
+        };
+
+        player.resources -= investment;
+        let target = landing.owner.unwrap_or(usize::MAX);
+        if let Some(recording) = &mut self.recording {
+            recording.attacks.push(super::AttackRecord { tick: self.tick, source, target, investment });
+        }
+        crate::game_log!("Player {} sails {} cells to ({}, {}) with {}", source, route.len(), x, y, investment);
+        self.attack_movements.push(AttackMovement::naval(source, target, investment, route, (x, y)));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::game::PlayerCommand;
+    use crate::modules::terrain::{Terrain, TerrainMap};
+    use crate::modules::types::Player;
+
+    // Two islands split by a strait of water five cells wide
+    fn islands() -> GameState {
+        let config = GameConfig {
+            grid_width: 40,
+            grid_height: 20,
+            num_players: 2,
+            seed: Some(3),
+            ..GameConfig::default()
+        };
+        let mut state = GameState::new(config);
+        let mut terrain = TerrainMap::new(40, 20, Terrain::Water);
+        for y in 2..18 {
+            for x in (2..15).chain(20..38) {
+                terrain.set(x, y, Terrain::Plains);
+            }
+        }
+        state.set_terrain(terrain).unwrap();
+        state
+    }
+
+    #[test]
+    fn routes_cross_water_only() {
+        let mut state = islands();
+        for y in 2..18 {
+            for x in 2..15 {
+                state.grid[y][x] = Some(0);
+            }
+        }
+
+        let routes = state.sea_routes(0);
+        let landing = routes.landing(20, 10).copied().unwrap();
+        assert_eq!((landing.owner, landing.distance), (None, 5));
+        let route = routes.route_to(20, 10).unwrap();
+        assert_eq!(route.len(), landing.distance);
+        assert_eq!(route.first(), Some(&(15, 10)));
+        assert_eq!(route.last(), Some(&(19, 10)));
+        assert!(route.iter().all(|&(x, y)| !state.terrain.is_passable(x, y)));
+        // Inland cells of the other island have no shore to land on
+        assert!(routes.landing(25, 10).is_none());
+        assert!(routes.landings().iter().all(|l| l.x >= 20));
+    }
+
+    #[test]
+    fn boats_land_on_the_other_island() {
+        let mut state = islands();
+        // A nation on the east shore of the left island, the right one is empty
+        state.players.push(Player::new(0, 13, 10, &state.config));
+        state.create_initial_territory(13, 10, 0);
+        state.apply_command(0, PlayerCommand::Claim).unwrap();
+        let resources = state.players[0].resources;
+
+        assert!(!state.start_naval_attack(0, 25, 10, 200));
+        assert!(state.start_naval_attack(0, 20, 10, 200));
+        assert_eq!(state.players[0].resources, resources - 200);
+        assert!(state.attack_movements[0].is_at_sea());
+
+        // Five water cells at three per tick, the boat lands on the second tick
+        state.update();
+        assert_eq!(state.grid[10][20], None);
+        state.update();
+        assert_eq!(state.grid[10][20], Some(0));
+        assert!(!state.attack_movements[0].is_at_sea());
+        assert!(state.attack_movements[0].investment < 200 - 5);
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::modules::config::GameConfig;
+use crate::modules::terrain::TerrainMap;
+use crate::modules::types::{Elimination, Player};
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+use super::commands::CommandRecord;
+use super::state::GameState;
+
+pub const REPLAY_VERSION: u32 = 2;
+
+// Playback keeps a copy of the game every CHECKPOINT_INTERVAL ticks so seeking
+// backwards does not have to re-simulate from the first tick
+const CHECKPOINT_INTERVAL: u64 = 1000;
+const MAX_CHECKPOINTS: usize = 32;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct AttackRecord {
+    pub tick: u64,
+    pub source: usize,
+    pub target: usize,
+    pub investment: i32,
+}
+
+// Everything needed to re-run a game: the setup, then one RNG seed per tick and the
+// commands human players sent in between. Attacks and eliminations are what those
+// ticks produced, kept for inspection.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ReplayLog {
+    pub version: u32,
+    pub config: GameConfig,
+    pub seed: u64,
+    pub terrain: TerrainMap,
+    pub placement: Vec<Player>,
+    pub tick_seeds: Vec<u64>,
+    #[serde(default)]
+    pub commands: Vec<CommandRecord>,
+    pub attacks: Vec<AttackRecord>,
+    pub eliminations: Vec<Elimination>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(PathBuf, std::io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(path, err) => write!(f, "replay file {}: {}", path.display(), err),
+            ReplayError::Format(err) => write!(f, "malformed replay: {}", err),
+            ReplayError::UnsupportedVersion(version) => write!(
+                f, "replay version {} is not supported (expected {})", version, REPLAY_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl ReplayLog {
+    pub fn total_ticks(&self) -> u64 {
+        self.tick_seeds.len() as u64
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let contents = serde_json::to_string(self).map_err(|e| ReplayError::Format(e.to_string()))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| ReplayError::Io(path.to_path_buf(), e))?;
+        }
+        fs::write(path, contents).map_err(|e| ReplayError::Io(path.to_path_buf(), e))
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self, ReplayError> {
+        let log: ReplayLog = serde_json::from_str(contents).map_err(|e| ReplayError::Format(e.to_string()))?;
+        if log.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(log.version));
+        }
+        Ok(log)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let contents = fs::read_to_string(path).map_err(|e| ReplayError::Io(path.to_path_buf(), e))?;
+        Self::from_json(&contents)
+    }
+
+    // Rebuilds the game as it was right after initialize_players
+    fn initial_state(&self) -> GameState {
+        let config = GameConfig { seed: Some(self.seed), ..self.config.clone() };
+        let mut state = GameState::new(config);
+        state.terrain = self.terrain.clone();
+        for player in &self.placement {
+            state.players.push(player.clone());
+            state.create_initial_territory(player.x, player.y, player.id);
+        }
+        state
+    }
+}
+
+impl GameState {
+    // Must be called before the first tick, the placement is taken from the current players
+    pub fn start_recording(&mut self) {
+        debug_assert_eq!(self.tick, 0, "recording has to start before the first tick");
+        self.recording = Some(ReplayLog {
+            version: REPLAY_VERSION,
+            config: self.config.clone(),
+            seed: self.seed,
+            terrain: self.terrain.clone(),
+            placement: self.players.clone(),
+            tick_seeds: Vec::new(),
+            commands: Vec::new(),
+            attacks: Vec::new(),
+            eliminations: Vec::new(),
+        });
+    }
+
+    pub fn replay_log(&self) -> Option<ReplayLog> {
+        self.recording.as_ref().map(|recording| ReplayLog {
+            eliminations: self.eliminations.clone(),
+            ..recording.clone()
+        })
+    }
+}
+
+pub struct ReplayPlayer {
+    log: ReplayLog,
+    state: GameState,
+    checkpoints: Vec<GameState>,
+}
+
+impl ReplayPlayer {
+    pub fn new(log: ReplayLog) -> Self {
+        let state = log.initial_state();
+        let checkpoints = vec![state.clone()];
+        ReplayPlayer { log, state, checkpoints }
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    pub fn log(&self) -> &ReplayLog {
+        &self.log
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.state.tick
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.state.tick >= self.log.total_ticks()
+    }
+
+    // Advances one recorded tick, returns false once the end of the replay is reached
+    pub fn step(&mut self) -> bool {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "replay_step");
+        let Some(&tick_seed) = self.log.tick_seeds.get(self.state.tick as usize) else {
+            return false;
+        };
+        // Commands are sorted by tick, apply the ones that arrived before this tick ran
+        let tick = self.state.tick;
+        let start = self.log.commands.partition_point(|record| record.tick < tick);
+        for record in self.log.commands[start..].iter().take_while(|record| record.tick == tick) {
+            if let Err(e) = self.state.apply_command(record.player, record.command) {
+                crate::game_log!("Replay command of player {} at tick {} failed: {}", record.player, tick, e);
+            }
+        }
+        self.state.update_with_seed(tick_seed);
+
+        let tick = self.state.tick;
+        if tick.is_multiple_of(CHECKPOINT_INTERVAL) &&
+           self.checkpoints.len() as u64 == tick / CHECKPOINT_INTERVAL &&
+           self.checkpoints.len() < MAX_CHECKPOINTS {
+            self.checkpoints.push(self.state.clone());
+        }
+        true
+    }
+
+    pub fn seek(&mut self, tick: u64) {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "replay_seek");
+        let tick = tick.min(self.log.total_ticks());
+
+        // Jump to the closest checkpoint at or before the target when it saves work
+        let checkpoint = ((tick / CHECKPOINT_INTERVAL) as usize).min(self.checkpoints.len() - 1);
+        let checkpoint_tick = self.checkpoints[checkpoint].tick;
+        if tick < self.state.tick || checkpoint_tick > self.state.tick {
+            self.state = self.checkpoints[checkpoint].clone();
+        }
+
+        while self.state.tick < tick && self.step() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::game::PlayerCommand;
+    use crate::modules::game::testing::test_game;
+
+    fn recorded_game(ticks: usize) -> GameState {
+        let mut state = test_game(120, 90, 6, 21);
+        state.start_recording();
+        for _ in 0..ticks {
+            state.update();
+        }
+        state
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_game() {
+        let original = recorded_game(120);
+        let log = ReplayLog::from_json(&serde_json::to_string(&original.replay_log().unwrap()).unwrap()).unwrap();
+        assert_eq!(log.total_ticks(), 120);
+
+        let mut player = ReplayPlayer::new(log);
+        player.seek(120);
+        assert!(player.is_at_end());
+        assert_eq!(player.state().grid, original.grid);
+        assert_eq!(player.state().eliminations, original.eliminations);
+    }
+
+    #[test]
+    fn replay_applies_player_commands() {
+        let mut original = recorded_game(10);
+        original.apply_command(2, PlayerCommand::Claim).unwrap();
+        for _ in 0..20 {
+            original.update();
+        }
+        original.apply_command(2, PlayerCommand::Attack { target: None, percent: 80.0, toward: None }).unwrap();
+        for _ in 0..20 {
+            original.update();
+        }
+
+        let mut player = ReplayPlayer::new(original.replay_log().unwrap());
+        player.seek(50);
+        assert_eq!(player.state().grid, original.grid);
+        assert_eq!(player.state().players.len(), original.players.len());
+    }
+
+    #[test]
+    fn seeking_backwards_matches_playing_forward() {
+        let midway = recorded_game(50);
+        let mut player = ReplayPlayer::new(recorded_game(120).replay_log().unwrap());
+        player.seek(120);
+        player.seek(50);
+        assert_eq!(player.tick(), 50);
+        assert_eq!(player.state().grid, midway.grid);
+    }
+}
@@ -0,0 +1,77 @@
+use serde::Serialize;
+use crate::modules::types::PlayerKind;
+use super::commands::CommandError;
+use super::state::GameState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct LeaderboardEntry {
+    // 1 for the largest nation
+    pub rank: usize,
+    pub player: usize,
+    pub area: i32,
+    pub resources: i32,
+}
+
+impl GameState {
+    // Renames and recolours a nation, for players who picked their own in the lobby
+    pub fn set_identity(&mut self, player_id: usize, name: &str, color: &str) -> Result<(), CommandError> {
+        let player = self.players.iter_mut()
+            .find(|p| p.id == player_id)
+            .ok_or(CommandError::NotInGame(player_id))?;
+        player.name = name.to_string();
+        player.color = color.to_string();
+        Ok(())
+    }
+
+    pub(super) fn set_kind(&mut self, player_id: usize, kind: PlayerKind) {
+        if let Some(player) = self.players.iter_mut().find(|p| p.id == player_id) {
+            player.kind = kind;
+        }
+    }
+
+    // Nations still standing ranked by area, ties going to the lower id like
+    // in the standings
+    pub fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let mut players: Vec<_> = self.players.iter().collect();
+        players.sort_by(|a, b| b.area.cmp(&a.area).then(a.id.cmp(&b.id)));
+        players.iter().enumerate()
+            .map(|(i, p)| LeaderboardEntry { rank: i + 1, player: p.id, area: p.area, resources: p.resources })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::game::PlayerCommand;
+    use crate::modules::game::testing::{seeded_game, test_config};
+    use crate::modules::types::default_color;
+
+    #[test]
+    fn nations_carry_an_identity_and_a_rank() {
+        let config = GameConfig { teams: vec![1, 1], ..test_config(60, 40, 3, 2) };
+        let mut state = seeded_game(config.clone());
+        let player = |state: &GameState, id: usize| state.players.iter().find(|p| p.id == id).cloned().unwrap();
+
+        // Bots are numbered, teammates get two shades of the team's hue
+        assert_eq!(player(&state, 2).name, "Nation 2");
+        assert_eq!(player(&state, 2).color, "#0000ff");
+        assert_eq!((default_color(0, &config), default_color(1, &config)), ("#c20a0a".into(), "#f31616".into()));
+        assert_eq!(player(&state, 0).color, "#c20a0a");
+
+        state.apply_command(1, PlayerCommand::Claim).unwrap();
+        state.set_identity(1, "Ada", "#123456").unwrap();
+        assert_eq!(player(&state, 1).kind, PlayerKind::Human);
+        assert_eq!((player(&state, 1).name.as_str(), player(&state, 1).color.as_str()), ("Ada", "#123456"));
+        state.apply_command(1, PlayerCommand::Release).unwrap();
+        assert_eq!(player(&state, 1).kind, PlayerKind::Bot);
+        assert_eq!(state.set_identity(7, "Bob", "#000000"), Err(CommandError::NotInGame(7)));
+
+        state.players[0].area = 5;
+        state.players[1].area = 9;
+        state.players[2].area = 5;
+        let ranking: Vec<_> = state.leaderboard().iter().map(|e| (e.rank, e.player)).collect();
+        assert_eq!(ranking, vec![(1, 1), (2, 0), (3, 2)]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+use super::commands::PlayerCommand;
+use super::lifecycle::LifecycleEvent;
+use super::state::GameState;
+
+// Bump whenever a serialized field of GameState changes meaning or layout
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    state: &'a GameState,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(PathBuf, std::io::Error),
+    Format(String),
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(path, err) => write!(f, "snapshot file {}: {}", path.display(), err),
+            SnapshotError::Format(err) => write!(f, "malformed snapshot: {}", err),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f, "snapshot version {} is not supported (expected {})", version, SNAPSHOT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl GameState {
+    // Snapshots carry the grid, players, in-flight attacks and RNG state, so a restored
+    // game continues exactly as if it had never stopped
+    pub fn to_snapshot_string(&self) -> Result<String, SnapshotError> {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "snapshot_serialize");
+        serde_json::to_string(&SnapshotRef { version: SNAPSHOT_VERSION, state: self })
+            .map_err(|e| SnapshotError::Format(e.to_string()))
+    }
+
+    pub fn from_snapshot_str(contents: &str) -> Result<Self, SnapshotError> {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "snapshot_deserialize");
+        let mut value: serde_json::Value = serde_json::from_str(contents)
+            .map_err(|e| SnapshotError::Format(e.to_string()))?;
+
+        let version = value.get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| SnapshotError::Format("missing version".into()))?;
+        if version != SNAPSHOT_VERSION as u64 {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let state = value.get_mut("state")
+            .map(serde_json::Value::take)
+            .ok_or_else(|| SnapshotError::Format("missing state".into()))?;
+        serde_json::from_value(state).map_err(|e| SnapshotError::Format(e.to_string()))
+    }
+
+    // Swaps a restored game in for this one. Connected clients keep their nations
+    // as long as they exist in the restored game, and the swap is announced like
+    // a new game so they get its terrain and a keyframe.
+    pub fn restore(&mut self, restored: GameState) {
+        let humans = std::mem::take(&mut self.human_players);
+        *self = restored;
+        for player in humans {
+            let _ = self.apply_command(player, PlayerCommand::Claim);
+        }
+        self.lifecycle_events.push(LifecycleEvent::NewGame);
+        self.lifecycle_events.push(LifecycleEvent::PhaseChanged(self.phase));
+    }
+
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), SnapshotError> {
+        let contents = self.to_snapshot_string()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| SnapshotError::Io(path.to_path_buf(), e))?;
+        }
+        fs::write(path, contents).map_err(|e| SnapshotError::Io(path.to_path_buf(), e))?;
+        crate::game_log!("Saved snapshot of tick {} to {}", self.tick, path.display());
+        Ok(())
+    }
+
+    pub fn load_snapshot(path: &Path) -> Result<Self, SnapshotError> {
+        let contents = fs::read_to_string(path).map_err(|e| SnapshotError::Io(path.to_path_buf(), e))?;
+        let state = Self::from_snapshot_str(&contents)?;
+        crate::game_log!("Loaded snapshot of tick {} (seed {}) from {}", state.tick, state.seed, path.display());
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::game::testing::test_game;
+
+    fn new_game() -> GameState {
+        test_game(120, 90, 6, 11)
+    }
+
+    #[test]
+    fn restored_game_continues_identically() {
+        let mut uninterrupted = new_game();
+        for _ in 0..150 {
+            uninterrupted.update();
+        }
+
+        let mut first_half = new_game();
+        for _ in 0..75 {
+            first_half.update();
+        }
+        let snapshot = first_half.to_snapshot_string().unwrap();
+        let mut restored = GameState::from_snapshot_str(&snapshot).unwrap();
+        for _ in 0..75 {
+            restored.update();
+        }
+
+        assert_eq!(restored.tick, uninterrupted.tick);
+        assert_eq!(restored.grid, uninterrupted.grid);
+        assert_eq!(restored.eliminations, uninterrupted.eliminations);
+        assert_eq!(restored.rng, uninterrupted.rng);
+        let summary = |state: &GameState| -> Vec<_> {
+            state.players.iter().map(|p| (p.id, p.resources, p.area)).collect()
+        };
+        assert_eq!(summary(&restored), summary(&uninterrupted));
+    }
+
+    #[test]
+    fn recordings_go_on_after_a_restore() {
+        let mut plain = new_game();
+        plain.update();
+        assert!(plain.to_snapshot_string().unwrap().contains("\"recording\":null"));
+
+        let mut recorded = new_game();
+        recorded.start_recording();
+        for _ in 0..20 {
+            recorded.update();
+        }
+        let snapshot = recorded.to_snapshot_string().unwrap();
+        let mut restored = GameState::from_snapshot_str(&snapshot).unwrap();
+        assert_eq!(restored.replay_log().unwrap().total_ticks(), 20);
+        for _ in 0..20 {
+            recorded.update();
+            restored.update();
+        }
+        assert_eq!(restored.replay_log().unwrap().tick_seeds, recorded.replay_log().unwrap().tick_seeds);
+        assert_eq!(restored.replay_log().unwrap().total_ticks(), 40);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let snapshot = new_game().to_snapshot_string().unwrap()
+            .replacen(&format!("\"version\":{}", SNAPSHOT_VERSION), "\"version\":999", 1);
+        assert!(matches!(
+            GameState::from_snapshot_str(&snapshot),
+            Err(SnapshotError::UnsupportedVersion(999))
+        ));
+    }
+}
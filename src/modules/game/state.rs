@@ -1,24 +1,102 @@
-use std::collections::HashSet;
-use serde::Serialize;
-use crate::modules::config::*;
-use crate::modules::types::{Grid, Players, AttackMovement};
+use std::collections::{BTreeSet, HashSet};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use crate::modules::config::GameConfig;
+use crate::modules::terrain::{Terrain, TerrainError, TerrainMap};
+use crate::modules::types::{Grid, Players, AttackFocus, AttackMovement, Elimination, GameRng};
+use super::diplomacy::Diplomacy;
+use super::enclaves::EnclaveMarks;
+use super::lifecycle::{GameResult, LifecycleEvent, Phase};
+use super::replay::{AttackRecord, ReplayLog};
 use crate::modules::timing::ExecutionTimer;
 use crate::TIMING_STATS;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub grid: Grid,
+    pub terrain: TerrainMap,
     pub players: Players,
     pub(crate) attack_movements: Vec<AttackMovement>,
+    pub config: GameConfig,
+    pub tick: u64,
+    pub eliminations: Vec<Elimination>,
+    pub seed: u64,
+    pub(crate) rng: GameRng,
+    // Kept in snapshots so a restored game can go on recording, which makes the
+    // snapshots of recorded games grow by one seed per tick played
+    #[serde(default)]
+    pub recording: Option<ReplayLog>,
+    #[serde(default)]
+    pub diplomacy: Diplomacy,
+    #[serde(default)]
+    pub phase: Phase,
+    // Set once the game is won
+    #[serde(default)]
+    pub result: Option<GameResult>,
+    // Nations driven by connected clients rather than the bot logic. Claims belong to
+    // connections, so they are not part of snapshots.
+    #[serde(skip)]
+    pub(crate) human_players: BTreeSet<usize>,
+    // Authored starting positions from the map, only used by initialize_players
+    #[serde(skip)]
+    pub(crate) spawn_points: Vec<(usize, usize)>,
+    // Cells whose owner changed during the last tick with the owner they had
+    // before it, used to broadcast deltas and to follow conquests
+    #[serde(skip)]
+    pub(crate) changed_cells: Vec<(usize, usize, Option<usize>)>,
+    #[serde(skip)]
+    pub(crate) lifecycle_events: Vec<LifecycleEvent>,
+    // Scratch space of capture_enclaves
+    #[serde(skip)]
+    pub(crate) enclave_marks: EnclaveMarks,
 }
 
 impl GameState {
-    pub fn new() -> Self {
+    pub fn new(config: GameConfig) -> Self {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "game_state_creation");
-        let grid = vec![vec![None; GRID_WIDTH]; GRID_HEIGHT];
+        let grid = vec![vec![None; config.grid_width]; config.grid_height];
+        let terrain = TerrainMap::new(config.grid_width, config.grid_height, Terrain::Plains);
         let players = Vec::new();
         let attack_movements = Vec::new();
-        GameState { grid, players, attack_movements }
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        crate::game_log!("Using simulation seed {}", seed);
+        let rng = GameRng::seed_from_u64(seed);
+        GameState {
+            grid,
+            terrain,
+            players,
+            attack_movements,
+            config,
+            tick: 0,
+            eliminations: Vec::new(),
+            seed,
+            rng,
+            recording: None,
+            diplomacy: Diplomacy::default(),
+            phase: Phase::Running,
+            result: None,
+            human_players: BTreeSet::new(),
+            spawn_points: Vec::new(),
+            changed_cells: Vec::new(),
+            lifecycle_events: Vec::new(),
+            enclave_marks: EnclaveMarks::default(),
+        }
+    }
+
+    // Replaces the all-plains default, has to happen before initialize_players
+    pub fn set_terrain(&mut self, terrain: TerrainMap) -> Result<(), TerrainError> {
+        if terrain.width() != self.config.grid_width || terrain.height() != self.config.grid_height {
+            return Err(TerrainError::new(format!(
+                "map is {}x{} but the grid is {}x{}",
+                terrain.width(), terrain.height(), self.config.grid_width, self.config.grid_height
+            )));
+        }
+        self.terrain = terrain;
+        Ok(())
+    }
+
+    pub fn changed_cells(&self) -> &[(usize, usize, Option<usize>)] {
+        &self.changed_cells
     }
 
     pub fn is_position_available(&self, x: i32, y: i32, radius: i32) -> bool {
@@ -27,34 +105,70 @@ impl GameState {
             for dx in -radius..=radius {
                 let new_x = x + dx;
                 let new_y = y + dy;
-                if new_x >= 0 && new_x < GRID_WIDTH as i32 &&
-                   new_y >= 0 && new_y < GRID_HEIGHT as i32 {
-                    if self.grid[new_y as usize][new_x as usize].is_some() {
-                        return false;
-                    }
+                if new_x >= 0 && new_x < self.config.grid_width as i32 &&
+                   new_y >= 0 && new_y < self.config.grid_height as i32 &&
+                   (self.grid[new_y as usize][new_x as usize].is_some() ||
+                    !self.terrain.is_passable(new_x as usize, new_y as usize)) {
+                    return false;
                 }
             }
         }
         true
     }
 
+    // A game is over once one of the win conditions is met, see `victory`
+    pub fn is_finished(&self) -> bool {
+        self.victory().is_some()
+    }
+
+    pub fn teammates(&self, a: usize, b: usize) -> bool {
+        a != b && self.config.team_for(a).is_some_and(|team| self.config.team_for(b) == Some(team))
+    }
+
+    // Whether `a` must leave `b` alone, as teammates or through a treaty
+    pub fn is_friendly(&self, a: usize, b: usize) -> bool {
+        self.teammates(a, b) || self.diplomacy.at_peace(a, b)
+    }
+
+    // The team every nation still standing belongs to, if there is one
+    pub fn winning_team(&self) -> Option<usize> {
+        let team = self.players.first()?.team?;
+        self.players.iter().all(|p| p.team == Some(team)).then_some(team)
+    }
+
+    pub fn winner(&self) -> Option<usize> {
+        match self.players.as_slice() {
+            [player] => Some(player.id),
+            _ => None,
+        }
+    }
+
     pub fn get_active_player_ids(&self) -> HashSet<usize> {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "get_active_players");
         self.players.iter().map(|p| p.id).collect()
     }
 
-    pub fn start_attack(&mut self, source: usize, target: usize, investment: i32) {
+    // `toward` aims the attack at a point instead of the whole shared border
+    pub fn start_attack(&mut self, source: usize, target: usize, investment: i32, toward: Option<(usize, usize)>) {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "start_attack");
+        if self.is_friendly(source, target) {
+            return;
+        }
         if let Some(player) = self.players.iter_mut().find(|p| p.id == source) {
             player.resources -= investment;
-            let attack = AttackMovement::new(source, target, investment);
+            if let Some(recording) = &mut self.recording {
+                recording.attacks.push(AttackRecord { tick: self.tick, source, target, investment });
+            }
+            let focus = toward.map(|point| AttackFocus { point, width: self.config.targeted_front_width });
+            let attack = AttackMovement::new(source, target, investment).with_focus(focus);
             
             // Handle counter-attacks
             let mut should_add = true;
             let mut movements_to_remove = Vec::new();
             
             for (i, existing_attack) in self.attack_movements.iter_mut().enumerate() {
-                if existing_attack.source == target && existing_attack.target == source {
+                // Boats still at sea cannot be met on the border
+                if existing_attack.source == target && existing_attack.target == source && !existing_attack.is_at_sea() {
                     // Counter-attack scenario
                     let min_investment = std::cmp::min(attack.investment, existing_attack.investment);
                     
@@ -95,12 +209,11 @@ impl GameState {
     pub fn eliminate_player(&mut self, player_id: usize) {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "eliminate_player");
         // Remove player's territory
-        for row in &mut self.grid {
-            for cell in row {
-                if let Some(id) = cell {
-                    if *id == player_id {
-                        *cell = None;
-                    }
+        for (y, row) in self.grid.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                if *cell == Some(player_id) {
+                    self.changed_cells.push((x, y, *cell));
+                    *cell = None;
                 }
             }
         }
@@ -114,10 +227,11 @@ impl GameState {
     pub fn update_grid(&mut self) {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "update_grid");
         let active_players = self.get_active_player_ids();
-        for row in &mut self.grid {
-            for cell in row {
+        for (y, row) in self.grid.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
                 if let Some(id) = cell {
                     if !active_players.contains(id) {
+                        self.changed_cells.push((x, y, Some(*id)));
                         *cell = None;
                     }
                 }
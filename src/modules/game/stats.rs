@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, VecDeque};
+use serde::Serialize;
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+use super::state::GameState;
+
+// Ticks of history kept for every nation, enough for a sparkline
+pub const STATS_HISTORY: usize = 120;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct PlayerStats {
+    pub player: usize,
+    pub area: i32,
+    pub resources: i32,
+    // Resources earned per tick from connected land and interest
+    pub income: i32,
+    // Attacks under way, boats still at sea included
+    pub attacks: usize,
+    // Cells taken during the tick, empty land included, and cells lost to other
+    // nations or back to empty land
+    pub gained: i32,
+    pub lost: i32,
+    // Nations finished off this game
+    pub kills: u32,
+}
+
+// The last ticks of a nation's stats, oldest first
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StatsHistory {
+    pub player: usize,
+    pub area: Vec<i32>,
+    pub resources: Vec<i32>,
+    pub income: Vec<i32>,
+    pub gained: Vec<i32>,
+    pub lost: Vec<i32>,
+}
+
+// Follows a game tick by tick to work out what each nation did. Cells changing
+// hands come from the changed cells of the tick and the owners they had before
+// it. The nation that last took cells from another is credited when it falls.
+#[derive(Default)]
+pub struct StatsTracker {
+    seed: u64,
+    tick: Option<u64>,
+    last_attacker: BTreeMap<usize, usize>,
+    kills: BTreeMap<usize, u32>,
+    current: Vec<PlayerStats>,
+    history: BTreeMap<usize, VecDeque<PlayerStats>>,
+}
+
+impl StatsTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Takes the state after a server tick, returns whether the stats moved on.
+    // A new game, a restored snapshot or skipped ticks start the tracking over.
+    pub fn observe(&mut self, state: &GameState) -> bool {
+        let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "stats_observe");
+        let same_game = self.seed == state.seed && self.tick.is_some();
+        if same_game && self.tick == Some(state.tick) {
+            return false;
+        }
+
+        let mut gained: BTreeMap<usize, i32> = BTreeMap::new();
+        let mut lost: BTreeMap<usize, i32> = BTreeMap::new();
+        if same_game && self.tick.is_some_and(|tick| tick + 1 == state.tick) {
+            for &(x, y, before) in state.changed_cells() {
+                let after = state.grid[y][x];
+                if before == after {
+                    continue;
+                }
+                if let Some(id) = before {
+                    *lost.entry(id).or_insert(0) += 1;
+                }
+                if let Some(id) = after {
+                    *gained.entry(id).or_insert(0) += 1;
+                    if let Some(victim) = before {
+                        self.last_attacker.insert(victim, id);
+                    }
+                }
+            }
+            for elimination in state.eliminations.iter().filter(|e| e.tick == state.tick) {
+                if let Some(killer) = self.last_attacker.remove(&elimination.player) {
+                    *self.kills.entry(killer).or_insert(0) += 1;
+                }
+                self.history.remove(&elimination.player);
+            }
+        } else {
+            *self = StatsTracker { seed: state.seed, ..Self::default() };
+        }
+        self.tick = Some(state.tick);
+
+        self.current = state.players.iter()
+            .map(|p| PlayerStats {
+                player: p.id,
+                area: p.area,
+                resources: p.resources,
+                income: p.income(&state.config),
+                attacks: state.attack_movements.iter().filter(|a| a.source == p.id).count(),
+                gained: gained.get(&p.id).copied().unwrap_or(0),
+                lost: lost.get(&p.id).copied().unwrap_or(0),
+                kills: self.kills.get(&p.id).copied().unwrap_or(0),
+            })
+            .collect();
+        self.current.sort_by_key(|stats| stats.player);
+        for stats in &self.current {
+            let history = self.history.entry(stats.player).or_default();
+            if history.len() == STATS_HISTORY {
+                history.pop_front();
+            }
+            history.push_back(*stats);
+        }
+        true
+    }
+
+    // Tick of the latest stats, None before the first one
+    pub fn tick(&self) -> Option<u64> {
+        self.tick
+    }
+
+    // Nations still standing by id
+    pub fn current(&self) -> &[PlayerStats] {
+        &self.current
+    }
+
+    // The last `window` ticks of every nation still standing
+    pub fn history(&self, window: usize) -> Vec<StatsHistory> {
+        self.history.iter()
+            .map(|(&player, samples)| {
+                let samples = samples.iter().skip(samples.len().saturating_sub(window));
+                let mut history = StatsHistory { player, ..StatsHistory::default() };
+                for stats in samples {
+                    history.area.push(stats.area);
+                    history.resources.push(stats.resources);
+                    history.income.push(stats.income);
+                    history.gained.push(stats.gained);
+                    history.lost.push(stats.lost);
+                }
+                history
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::game::testing::test_game;
+    use crate::modules::types::Grid;
+
+    #[test]
+    fn stats_follow_conquests_and_kills() {
+        let mut state = test_game(80, 60, 4, 6);
+        let mut tracker = StatsTracker::new();
+        assert!(tracker.observe(&state));
+        assert!(!tracker.observe(&state));
+
+        for _ in 0..400 {
+            let before = state.grid.clone();
+            state.update();
+            assert!(tracker.observe(&state));
+            for stats in tracker.current() {
+                let player = state.players.iter().find(|p| p.id == stats.player).unwrap();
+                assert_eq!((stats.area, stats.resources), (player.area, player.resources));
+                let cells = |grid: &Grid, taken: bool| grid.iter().zip(&before).flat_map(|(row, old)| row.iter().zip(old))
+                    .filter(|&(new, old)| new != old && if taken { *new == Some(stats.player) } else { *old == Some(stats.player) })
+                    .count() as i32;
+                assert_eq!((stats.gained, stats.lost), (cells(&state.grid, true), cells(&state.grid, false)));
+            }
+        }
+
+        let kills: u32 = tracker.current().iter().map(|s| s.kills).sum();
+        assert!(kills > 0 && kills as usize <= state.eliminations.len());
+        let history = tracker.history(10);
+        assert_eq!(history.len(), state.players.len());
+        assert!(history.iter().all(|h| h.area.len() == 10));
+        assert_eq!(history[0].area.last(), Some(&tracker.current()[0].area));
+
+        // Another game starts the tracking over
+        let next = test_game(80, 60, 4, 7);
+        assert!(tracker.observe(&next));
+        assert_eq!(tracker.tick(), Some(0));
+        assert!(tracker.history(STATS_HISTORY).iter().all(|h| h.area.len() == 1));
+    }
+}
@@ -0,0 +1,26 @@
+// Seeded games shared by the tests across the crate
+use crate::modules::config::GameConfig;
+use super::state::GameState;
+
+// A default config on a small grid with a fixed seed
+pub fn test_config(width: usize, height: usize, players: usize, seed: u64) -> GameConfig {
+    GameConfig {
+        grid_width: width,
+        grid_height: height,
+        num_players: players,
+        seed: Some(seed),
+        ..GameConfig::default()
+    }
+}
+
+// A game from test_config with its nations already placed
+pub fn test_game(width: usize, height: usize, players: usize, seed: u64) -> GameState {
+    seeded_game(test_config(width, height, players, seed))
+}
+
+// Places the nations of a game built from any config
+pub fn seeded_game(config: GameConfig) -> GameState {
+    let mut state = GameState::new(config);
+    state.initialize_players();
+    state
+}
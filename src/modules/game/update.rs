@@ -1,41 +1,93 @@
-use crate::modules::config::*;
 use super::state::GameState;
 use std::collections::HashMap;
+use rand::{Rng, SeedableRng};
+use crate::modules::strategy::{DiplomaticAction, PlayerView};
+use crate::modules::types::{Elimination, GameRng};
 use crate::modules::timing::ExecutionTimer;
 use crate::TIMING_STATS;
 
 impl GameState {
     pub fn update(&mut self) {
+        let tick_seed = self.rng.gen();
+        self.update_with_seed(tick_seed);
+    }
+
+    // Each tick reseeds the RNG from its own seed, replays store these seeds to re-run ticks
+    pub fn update_with_seed(&mut self, tick_seed: u64) {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "game_update_full");
         
+        self.tick += 1;
+        self.rng = GameRng::seed_from_u64(tick_seed);
+        self.changed_cells.clear();
+        if let Some(recording) = &mut self.recording {
+            recording.tick_seeds.push(tick_seed);
+        }
+
+        self.update_diplomacy();
         self.process_player_updates();
         self.process_attack_movements();
+        self.capture_enclaves();
+        self.decay_fragments();
         self.update_player_areas();
         self.update_grid();
+
+        // A pixel can be taken by several attacks in one tick, report it once with
+        // the owner it had when the tick started. The sort is stable, so the first
+        // change of each pixel comes first.
+        self.changed_cells.sort_by_key(|&(x, y, _)| (x, y));
+        self.changed_cells.dedup_by_key(|&mut (x, y, _)| (x, y));
     }
 
     fn process_player_updates(&mut self) {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "process_player_updates");
         
-        let mut expansion_attempts = Vec::with_capacity(self.players.len());
-        
-        // Update resources and collect expansion attempts
         for player in &mut self.players {
-            player.update_resources();
-            
-            if player.try_expand() {
-                let investment = player.calculate_expansion_investment();
-                if investment > 0 {
-                    expansion_attempts.push((player.id, investment));
+            player.update_resources(&self.config);
+        }
+
+        // Views borrow the whole state, so the RNG is moved out while the bots decide
+        let mut rng = std::mem::replace(&mut self.rng, GameRng::seed_from_u64(0));
+        let mut orders = Vec::new();
+        let mut actions = Vec::new();
+        for player in &self.players {
+            // Human players expand through their own commands
+            if self.human_players.contains(&player.id) {
+                continue;
+            }
+            let view = PlayerView::new(self, player);
+            let strategy = self.config.strategy_for(player.id).strategy();
+            for action in strategy.diplomacy(&view, &mut rng) {
+                actions.push((player.id, action));
+            }
+            for order in strategy.decide(&view, &mut rng) {
+                if view.is_valid(&order) {
+                    orders.push((player.id, order));
                 }
             }
         }
-        
-        // Process expansion attempts in batch
-        for (player_id, investment) in expansion_attempts {
-            if let Some((target, is_empty)) = self.find_random_neighbor(player_id) {
-                let target_id = if is_empty { usize::MAX } else { target };
-                self.start_attack(player_id, target_id, investment);
+        self.rng = rng;
+
+        // Bots ask for whatever they like, moves that are no longer possible are dropped
+        for (player_id, action) in actions {
+            let _ = match action {
+                DiplomaticAction::Propose { to, treaty } => self.propose_treaty(player_id, to, treaty),
+                DiplomaticAction::Accept { from } => self.accept_treaty(player_id, from),
+                DiplomaticAction::Break { with } => self.break_treaty(player_id, with),
+            };
+        }
+
+        for (player_id, order) in orders {
+            // Earlier orders of the same player may have used up part of its resources
+            let resources = self.players.iter().find(|p| p.id == player_id).map_or(0, |p| p.resources);
+            let investment = order.investment.min(resources);
+            if investment <= 0 {
+                continue;
+            }
+            match order.landing {
+                Some((x, y)) => {
+                    self.start_naval_attack(player_id, x, y, investment);
+                }
+                None => self.start_attack(player_id, order.target.unwrap_or(usize::MAX), investment, order.toward),
             }
         }
     }
@@ -47,23 +99,47 @@ impl GameState {
         let mut attack_updates = Vec::new();
         let mut completed_attacks = Vec::new();
         let mut investments_to_return = HashMap::new();
+        let mut defender_losses: HashMap<usize, i32> = HashMap::new();
+
+        // Defense is fixed for the whole tick, whatever the defender loses meanwhile
+        let defense: HashMap<usize, i32> = self.players.iter()
+            .map(|p| (p.id, p.defense_cost(&self.config)))
+            .collect();
         
         // First pass: collect all updates
         {
             let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "attack_movement_collection");
             for (i, attack) in self.attack_movements.iter_mut().enumerate() {
+                // Boats spend their investment on the crossing, those that run out sink
+                let landed = attack.sail(self.config.boat_speed, self.config.naval_cost_per_cell);
+                if attack.investment <= 0 {
+                    completed_attacks.push(i);
+                    continue;
+                }
+                if !landed {
+                    continue;
+                }
                 if !attack.is_started {
-                    attack.start(&self.grid);
+                    attack.start(&self.grid, &self.terrain);
                 }
                 
-                let next_pixels = attack.get_next_pixels(&self.grid);
+                let next_pixels = attack.get_next_pixels(&self.grid, &self.terrain);
                 if next_pixels.is_empty() {
                     investments_to_return.insert(attack.source, attack.investment);
                     completed_attacks.push(i);
                 } else {
-                    let pixel_count = next_pixels.len();
+                    let terrain_cost: i32 = next_pixels.iter()
+                        .map(|&(x, y)| self.terrain.get(x, y).capture_cost(&self.config))
+                        .sum();
+                    // Both sides pay for every defended pixel, empty land is only terrain
+                    let defense_cost = defense.get(&attack.target).copied().unwrap_or(0) * next_pixels.len() as i32;
+                    if defense_cost > 0 {
+                        *defender_losses.entry(attack.target).or_insert(0) += defense_cost;
+                    }
+                    let cost = terrain_cost + defense_cost;
                     attack_updates.push((attack.source, next_pixels.clone()));
-                    attack.investment -= pixel_count as i32;
+                    attack.advance(next_pixels);
+                    attack.investment -= cost;
                     
                     if attack.investment <= 0 {
                         completed_attacks.push(i);
@@ -77,6 +153,7 @@ impl GameState {
             let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "attack_movement_updates");
             for (source, pixels) in attack_updates {
                 for (x, y) in pixels {
+                    self.changed_cells.push((x, y, self.grid[y][x]));
                     self.grid[y][x] = Some(source);
                 }
             }
@@ -92,6 +169,12 @@ impl GameState {
                     player.resources += investment;
                 }
             }
+
+            for (player_id, loss) in defender_losses {
+                if let Some(player) = self.players.iter_mut().find(|p| p.id == player_id) {
+                    player.resources = (player.resources - loss).max(0);
+                }
+            }
         }
     }
 
@@ -99,18 +182,16 @@ impl GameState {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "update_player_areas");
         
         // Use pre-allocated vector for better performance
-        let mut areas = vec![0; NUM_PLAYERS];
+        let mut areas = vec![0; self.config.num_players];
         
         // Process grid in chunks for better cache utilization
         {
             let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "area_calculation");
             for chunk in self.grid.chunks(64) {
                 for row in chunk {
-                    for cell in row {
-                        if let Some(id) = cell {
-                            if *id < NUM_PLAYERS {
-                                areas[*id] += 1;
-                            }
+                    for id in row.iter().flatten() {
+                        if *id < areas.len() {
+                            areas[*id] += 1;
                         }
                     }
                 }
@@ -121,7 +202,7 @@ impl GameState {
         let mut to_eliminate = Vec::new();
         for player in &mut self.players {
             let area = areas[player.id];
-            if area < MIN_AREA_THRESHOLD {
+            if area < self.config.min_area_threshold {
                 to_eliminate.push(player.id);
             } else {
                 player.area = area;
@@ -133,6 +214,8 @@ impl GameState {
             let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "player_elimination");
             for player_id in to_eliminate {
                 self.eliminate_player(player_id);
+                self.eliminations.push(Elimination { player: player_id, tick: self.tick });
+                self.human_players.remove(&player_id);
                 if let Some(pos) = self.players.iter().position(|p| p.id == player_id) {
                     self.players.swap_remove(pos);
                 }
@@ -140,3 +223,176 @@ impl GameState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::game::testing::test_game;
+    use crate::modules::terrain::{Terrain, TerrainMap};
+
+    fn run(seed: u64, ticks: usize) -> GameState {
+        let mut state = test_game(120, 90, 6, seed);
+        for _ in 0..ticks {
+            state.update();
+        }
+        state
+    }
+
+    #[test]
+    fn same_seed_produces_identical_grid() {
+        let first = run(7, 200);
+        let second = run(7, 200);
+        assert_eq!(first.grid, second.grid);
+
+        let first_resources: Vec<_> = first.players.iter().map(|p| (p.id, p.resources, p.area)).collect();
+        let second_resources: Vec<_> = second.players.iter().map(|p| (p.id, p.resources, p.area)).collect();
+        assert_eq!(first_resources, second_resources);
+    }
+
+    #[test]
+    fn changed_cells_cover_every_grid_change() {
+        let mut state = run(3, 0);
+        let mut mirror = state.grid.clone();
+        for _ in 0..150 {
+            state.update();
+            for &(x, y, before) in state.changed_cells() {
+                assert_eq!(before, mirror[y][x], "wrong previous owner at ({}, {})", x, y);
+                mirror[y][x] = state.grid[y][x];
+            }
+            assert_eq!(mirror, state.grid, "delta missed a change at tick {}", state.tick);
+        }
+    }
+
+    #[test]
+    fn water_is_never_captured() {
+        let config = GameConfig {
+            grid_width: 120,
+            grid_height: 90,
+            num_players: 6,
+            seed: Some(5),
+            // Boats no nation can afford, so only land attacks are in play
+            naval_cost_per_cell: 1_000_000,
+            ..GameConfig::default()
+        };
+        let mut state = GameState::new(config);
+        // A lake in the middle and a water wall splitting off the right edge
+        let mut terrain = TerrainMap::new(120, 90, Terrain::Plains);
+        for y in 0..90 {
+            for x in 0..120 {
+                if x == 100 || ((40..80).contains(&x) && (30..60).contains(&y)) {
+                    terrain.set(x, y, Terrain::Water);
+                }
+            }
+        }
+        state.set_terrain(terrain).unwrap();
+        state.initialize_players();
+        let right_side: Vec<usize> = state.players.iter().filter(|p| p.x > 100).map(|p| p.id).collect();
+
+        for _ in 0..300 {
+            state.update();
+        }
+        for (y, row) in state.grid.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                if !state.terrain.is_passable(x, y) {
+                    assert_eq!(*cell, None, "water at ({}, {}) was captured", x, y);
+                } else if let Some(owner) = cell {
+                    assert_eq!(x > 100, right_side.contains(owner), "({}, {}) taken across the wall", x, y);
+                }
+            }
+        }
+    }
+
+    // Player 0 holds the left half of a 60x20 grid and player 1 the right half
+    fn split_board() -> GameState {
+        let config = GameConfig { grid_width: 60, grid_height: 20, num_players: 2, seed: Some(1), ..GameConfig::default() };
+        let mut state = GameState::new(config.clone());
+        state.players.push(crate::modules::types::Player::new(0, 10, 10, &config));
+        state.players.push(crate::modules::types::Player::new(1, 50, 10, &config));
+        for row in state.grid.iter_mut() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                *cell = Some(if x < 30 { 0 } else { 1 });
+            }
+        }
+        for player in &mut state.players {
+            player.area = 600;
+        }
+        state.human_players.extend([0, 1]);
+        state
+    }
+
+    #[test]
+    fn untargeted_attacks_keep_their_starting_front() {
+        let mut state = split_board();
+        state.start_attack(0, 1, 200, None);
+        for _ in 0..4 {
+            state.process_attack_movements();
+        }
+        // The border column and the one behind it fall, then the attack runs
+        // out of pixels next to its front and hands back what is left
+        for y in 0..20 {
+            assert_eq!((state.grid[y][30], state.grid[y][31], state.grid[y][32]), (Some(0), Some(0), Some(1)));
+        }
+        assert!(state.attack_movements.is_empty());
+    }
+
+    #[test]
+    fn defended_pixels_cost_both_sides() {
+        // 6000 resources on 600 pixels, a defense of 1 per pixel at the default factor
+        let mut state = split_board();
+        state.players[1].resources = 6000;
+        state.start_attack(0, 1, 200, None);
+        state.process_attack_movements();
+        // Two columns of 20 pixels on the first tick, plains plus defense
+        assert_eq!(state.attack_movements[0].investment, 200 - 40 * 2);
+        assert_eq!(state.players[1].resources, 6000 - 40);
+
+        // The defense is capped however rich the defender is
+        let mut state = split_board();
+        state.players[1].resources = 600_000;
+        state.start_attack(0, 1, 500, None);
+        state.process_attack_movements();
+        assert_eq!(state.attack_movements[0].investment, 500 - 40 * 11);
+        assert_eq!(state.players[1].resources, 600_000 - 40 * 10);
+    }
+
+    #[test]
+    fn empty_land_and_zero_factor_stay_flat() {
+        let mut state = split_board();
+        for y in 0..20 {
+            for x in 40..60 {
+                state.grid[y][x] = None;
+            }
+        }
+        state.start_attack(1, usize::MAX, 100, None);
+        state.process_attack_movements();
+        // The empty columns x = 40 and 41 at one per pixel
+        assert_eq!(state.attack_movements[0].investment, 100 - 40);
+
+        let mut state = split_board();
+        state.config.defense_factor = 0.0;
+        state.players[1].resources = 60_000;
+        state.start_attack(0, 1, 200, None);
+        state.process_attack_movements();
+        assert_eq!(state.attack_movements[0].investment, 200 - 40);
+        assert_eq!(state.players[1].resources, 60_000);
+    }
+
+    #[test]
+    fn targeted_attacks_grow_toward_their_point() {
+        let mut state = split_board();
+        state.start_attack(0, 1, 200, Some((55, 10)));
+        for _ in 0..20 {
+            state.process_attack_movements();
+        }
+        assert_eq!(state.grid[10][45], Some(0));
+        // The rest of the border is left alone
+        assert_eq!(state.grid[0][30], Some(1));
+        assert_eq!(state.grid[19][30], Some(1));
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        assert_ne!(run(7, 50).grid, run(8, 50).grid);
+    }
+}
@@ -0,0 +1,22 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// Batch runs print machine-readable output on stdout and silence game logs
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+#[macro_export]
+macro_rules! game_log {
+    ($($arg:tt)*) => {
+        if !$crate::modules::logging::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,245 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+use crate::modules::terrain::{Terrain, TerrainMap};
+
+pub const MAP_DIR: &str = "maps";
+
+// Map images are matched to the nearest of these colours, so anti-aliased edges
+// and slightly different shades from an image editor still come out right.
+// Spawn markers are plains; a blob of marker pixels counts as one spawn point.
+const PALETTE: [([u8; 3], Cell); 4] = [
+    ([0x3c, 0xa0, 0x3c], Cell::Terrain(Terrain::Plains)),
+    ([0x20, 0x50, 0xc0], Cell::Terrain(Terrain::Water)),
+    ([0x80, 0x80, 0x80], Cell::Terrain(Terrain::Mountain)),
+    ([0xff, 0x00, 0x00], Cell::Spawn),
+];
+
+// Transparent pixels are water, so maps can be drawn on an empty layer
+const TRANSPARENT_ALPHA: u8 = 128;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Cell {
+    Terrain(Terrain),
+    Spawn,
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(PathBuf, std::io::Error),
+    Decode(PathBuf, String),
+    Invalid(String),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Io(path, err) => write!(f, "could not read map {}: {}", path.display(), err),
+            MapError::Decode(path, err) => write!(f, "could not decode map {}: {}", path.display(), err),
+            MapError::Invalid(reason) => write!(f, "invalid map: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+#[derive(Clone, Debug)]
+pub struct GameMap {
+    pub terrain: TerrainMap,
+    // Authored starting positions, nations are placed by sections when there are none
+    pub spawns: Vec<(usize, usize)>,
+}
+
+impl GameMap {
+    // `name` is a map in MAP_DIR without its extension, or a path to any PNG file
+    pub fn path(name: &str) -> PathBuf {
+        if name.ends_with(".png") || name.contains('/') {
+            PathBuf::from(name)
+        } else {
+            PathBuf::from(MAP_DIR).join(format!("{}.png", name))
+        }
+    }
+
+    pub fn load(name: &str) -> Result<Self, MapError> {
+        let path = Self::path(name);
+        let file = File::open(&path).map_err(|e| MapError::Io(path.clone(), e))?;
+        let map = Self::from_png(BufReader::new(file)).map_err(|e| match e {
+            MapError::Decode(_, reason) => MapError::Decode(path.clone(), reason),
+            other => other,
+        })?;
+        crate::game_log!("Loaded map {} ({}x{}, {} spawn points)",
+            path.display(), map.terrain.width(), map.terrain.height(), map.spawns.len());
+        Ok(map)
+    }
+
+    pub fn from_png<R: Read>(reader: R) -> Result<Self, MapError> {
+        let decode_error = |e: png::DecodingError| MapError::Decode(PathBuf::new(), e.to_string());
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(decode_error)?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(decode_error)?;
+
+        let channels = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut terrain = TerrainMap::new(width, height, Terrain::Plains);
+        let mut spawn_pixels = vec![false; width * height];
+
+        for (i, pixel) in buf[..info.buffer_size()].chunks_exact(channels).enumerate() {
+            let (rgb, alpha) = match *pixel {
+                [gray] => ([gray; 3], 255),
+                [gray, alpha] => ([gray; 3], alpha),
+                [r, g, b] => ([r, g, b], 255),
+                [r, g, b, alpha] => ([r, g, b], alpha),
+                _ => unreachable!("8-bit images have one to four channels"),
+            };
+            let cell = if alpha < TRANSPARENT_ALPHA { Cell::Terrain(Terrain::Water) } else { nearest_cell(rgb) };
+            let (x, y) = (i % width, i / width);
+            match cell {
+                Cell::Terrain(kind) => terrain.set(x, y, kind),
+                Cell::Spawn => spawn_pixels[i] = true,
+            }
+        }
+
+        let spawns = spawn_points(&mut spawn_pixels, width, height);
+        Ok(GameMap { terrain, spawns })
+    }
+}
+
+fn nearest_cell(rgb: [u8; 3]) -> Cell {
+    let distance = |color: &[u8; 3]| -> i32 {
+        color.iter().zip(rgb).map(|(&a, b)| (a as i32 - b as i32).pow(2)).sum()
+    };
+    PALETTE.iter()
+        .min_by_key(|(color, _)| distance(color))
+        .map(|&(_, cell)| cell)
+        .expect("palette is not empty")
+}
+
+// Groups marker pixels into connected blobs and returns the pixel of each blob
+// closest to its centre, in row-major order of the blobs
+fn spawn_points(marked: &mut [bool], width: usize, height: usize) -> Vec<(usize, usize)> {
+    let mut spawns = Vec::new();
+    for start in 0..marked.len() {
+        if !marked[start] {
+            continue;
+        }
+        marked[start] = false;
+        let mut blob = vec![(start % width, start / width)];
+        let mut next = 0;
+        while next < blob.len() {
+            let (x, y) = blob[next];
+            next += 1;
+            let neighbors = [
+                (x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1),
+            ];
+            for (nx, ny) in neighbors {
+                if nx < width && ny < height && marked[ny * width + nx] {
+                    marked[ny * width + nx] = false;
+                    blob.push((nx, ny));
+                }
+            }
+        }
+
+        let (sum_x, sum_y) = blob.iter().fold((0, 0), |(sx, sy), &(x, y)| (sx + x, sy + y));
+        let (center_x, center_y) = (sum_x as f64 / blob.len() as f64, sum_y as f64 / blob.len() as f64);
+        let distance = |&(x, y): &(usize, usize)| (x as f64 - center_x).powi(2) + (y as f64 - center_y).powi(2);
+        let closest = blob.iter()
+            .copied()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .expect("blobs have at least one pixel");
+        spawns.push(closest);
+    }
+    spawns
+}
+
+// Map names shown to operators, the PNG files found in MAP_DIR
+pub fn list_maps() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(MAP_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("png") {
+                return None;
+            }
+            path.file_stem().and_then(|stem| stem.to_str()).map(str::to_string)
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::game::GameState;
+
+    fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+        let mut png_bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png_bytes, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(rgb).unwrap();
+        png_bytes
+    }
+
+    #[test]
+    fn decodes_terrain_and_spawns() {
+        // 4x3: a 2x2 spawn blob on the left, a lone spawn, water and a slightly off mountain grey
+        let rows: [[[u8; 3]; 4]; 3] = [
+            [[255, 0, 0], [255, 0, 0], [0x20, 0x50, 0xc0], [0x3c, 0xa0, 0x3c]],
+            [[255, 0, 0], [255, 0, 0], [0x20, 0x50, 0xc0], [250, 10, 10]],
+            [[0x3c, 0xa0, 0x3c], [0x3c, 0xa0, 0x3c], [0x88, 0x84, 0x80], [0x3c, 0xa0, 0x3c]],
+        ];
+        let rgb: Vec<u8> = rows.iter().flatten().flatten().copied().collect();
+        let map = GameMap::from_png(encode(4, 3, &rgb).as_slice()).unwrap();
+
+        assert_eq!(map.terrain.rows(), vec!["..~.", "..~.", "..^."]);
+        assert_eq!(map.spawns, vec![(0, 0), (3, 1)]);
+    }
+
+    #[test]
+    fn players_start_on_spawn_points() {
+        let mut terrain = TerrainMap::new(40, 30, Terrain::Plains);
+        for y in 0..30 {
+            terrain.set(20, y, Terrain::Water);
+        }
+        let map = GameMap { terrain, spawns: vec![(5, 5), (30, 20), (10, 25)] };
+        let config = GameConfig { num_players: 2, seed: Some(4), ..Default::default() };
+
+        let mut state = GameState::with_map(config.clone(), map.clone()).unwrap();
+        assert_eq!((state.config.grid_width, state.config.grid_height), (40, 30));
+        state.initialize_players();
+        assert_eq!(state.players.len(), 2);
+        for player in &state.players {
+            assert!(map.spawns.contains(&(player.x, player.y)));
+            assert_eq!(state.grid[player.y][player.x], Some(player.id));
+        }
+
+        let crowded = GameConfig { num_players: 4, ..config };
+        assert!(GameState::with_map(crowded, map).is_err());
+    }
+
+    #[test]
+    fn maps_without_spawns_must_fit_every_nation() {
+        let rgb: Vec<u8> = [0x3c, 0xa0, 0x3c].repeat(40 * 30);
+        let map = GameMap::from_png(encode(40, 30, &rgb).as_slice()).unwrap();
+        assert!(map.spawns.is_empty());
+
+        // Nations are spread over 7px sections, 5x4 of them on this map
+        let config = GameConfig { num_players: 20, seed: Some(4), ..Default::default() };
+        let mut state = GameState::with_map(config.clone(), map.clone()).unwrap();
+        state.initialize_players();
+        assert_eq!(state.players.len(), 20);
+
+        let crowded = GameConfig { num_players: 21, ..config };
+        match GameState::with_map(crowded, map) {
+            Err(MapError::Invalid(reason)) => assert!(reason.contains("21 players do not fit on a 40x30 grid")),
+            other => panic!("expected an invalid map, got {:?}", other.map(|_| ())),
+        }
+    }
+}
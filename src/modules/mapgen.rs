@@ -0,0 +1,400 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use rand::{Rng, SeedableRng};
+use crate::modules::config::GameConfig;
+use crate::modules::map::{GameMap, MapError};
+use crate::modules::terrain::{Terrain, TerrainMap};
+use crate::modules::timing::ExecutionTimer;
+use crate::modules::types::GameRng;
+use crate::TIMING_STATS;
+
+// Keeps map generation from consuming the game's own random stream, the same game
+// seed always produces the same map
+const MAPGEN_SEED_SALT: u64 = 0x6d61_7067_656e;
+
+const NOISE_OCTAVES: u32 = 5;
+// Weight of the continent shapes against the noise in the height map
+const CONTINENT_WEIGHT: f64 = 0.6;
+// Share of the land turned into mountains along noise ridges
+const MOUNTAIN_SHARE: f64 = 0.1;
+// Rivers leave a plains crossing every this many cells so they slow nations down
+// without cutting continents apart
+const FORD_SPACING: usize = 12;
+// Share of the land a spawn must reach within its radius, relaxed step by step
+// when the map has too few such places
+const SPAWN_REACH_SHARES: [f64; 4] = [0.7, 0.5, 0.3, 0.0];
+const SPAWN_CANDIDATES: usize = 600;
+// Spawns at least this many cells apart on either axis, so 3x3 starting
+// territories never overlap
+const MIN_SPAWN_DISTANCE: usize = 3;
+
+// Value noise over a hashed integer lattice, smooth enough for coastlines and
+// cheap enough to evaluate for every cell of a large grid
+struct Noise {
+    seed: u64,
+}
+
+impl Noise {
+    fn lattice(&self, x: i64, y: i64) -> f64 {
+        // splitmix64 finalizer
+        let mut z = self.seed
+            ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn value(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+        let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+        let (ix, iy) = (x0 as i64, y0 as i64);
+
+        let top = self.lattice(ix, iy) * (1.0 - tx) + self.lattice(ix + 1, iy) * tx;
+        let bottom = self.lattice(ix, iy + 1) * (1.0 - tx) + self.lattice(ix + 1, iy + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    // Sum of octaves, each twice the frequency and half the weight of the previous, in [0, 1)
+    fn fractal(&self, x: f64, y: f64) -> f64 {
+        let (mut total, mut amplitude, mut frequency, mut weights) = (0.0, 1.0, 1.0, 0.0);
+        for _ in 0..NOISE_OCTAVES {
+            total += self.value(x * frequency, y * frequency) * amplitude;
+            weights += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        total / weights
+    }
+}
+
+// Value below which `share` of `values` fall
+fn quantile(values: &[f64], share: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable_by(f64::total_cmp);
+    let index = ((sorted.len() as f64 * share) as usize).min(sorted.len().saturating_sub(1));
+    sorted.get(index).copied().unwrap_or(0.0)
+}
+
+const DIRECTIONS: [(i64, i64); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+fn neighbors(x: usize, y: usize, width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    DIRECTIONS.iter().filter_map(move |&(dx, dy)| {
+        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+        (nx >= 0 && ny >= 0 && (nx as usize) < width && (ny as usize) < height).then_some((nx as usize, ny as usize))
+    })
+}
+
+// Generates terrain for the configured grid size and picks one spawn per nation,
+// failing when the land leaves no room for all of them
+pub fn generate(config: &GameConfig, seed: u64) -> Result<GameMap, MapError> {
+    let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "generate_map");
+    let (width, height) = (config.grid_width, config.grid_height);
+    let mut rng = GameRng::seed_from_u64(seed ^ MAPGEN_SEED_SALT);
+
+    let heights = height_map(config, &mut rng);
+    let sea_level = quantile(&heights, 1.0 - config.land_ratio);
+    let mut terrain = TerrainMap::new(width, height, Terrain::Water);
+    for y in 0..height {
+        for x in 0..width {
+            if heights[y * width + x] > sea_level {
+                terrain.set(x, y, Terrain::Plains);
+            }
+        }
+    }
+
+    add_mountains(&mut terrain, &heights, sea_level, &mut rng);
+    add_rivers(&mut terrain, &heights, sea_level, config.rivers, &mut rng);
+    let spawns = place_spawns(&terrain, config.num_players, &mut rng).ok_or_else(|| MapError::Invalid(format!(
+        "generated {}x{} map with {:.0}% land has no room for {} spawn points",
+        width, height, config.land_ratio * 100.0, config.num_players
+    )))?;
+
+    crate::game_log!("Generated {}x{} map with {} continents, {} rivers and {} spawn points",
+        width, height, config.continents, config.rivers, spawns.len());
+    Ok(GameMap { terrain, spawns })
+}
+
+// Continent shapes around spread out centres, roughened by fractal noise
+fn height_map(config: &GameConfig, rng: &mut GameRng) -> Vec<f64> {
+    let (width, height) = (config.grid_width, config.grid_height);
+    let (w, h) = (width as f64, height as f64);
+    let noise = Noise { seed: rng.gen() };
+    let warp = Noise { seed: rng.gen() };
+    let feature_size = w.min(h) / 4.0;
+
+    // Farthest point sampling keeps continents apart
+    let margin = 0.15;
+    let mut centers: Vec<(f64, f64)> = Vec::with_capacity(config.continents);
+    let candidates: Vec<(f64, f64)> = (0..config.continents * 16)
+        .map(|_| (rng.gen_range(margin..1.0 - margin) * w, rng.gen_range(margin..1.0 - margin) * h))
+        .collect();
+    while centers.len() < config.continents {
+        let farthest = candidates.iter()
+            .copied()
+            .max_by(|a, b| {
+                let spread = |p: &(f64, f64)| centers.iter()
+                    .map(|c| (p.0 - c.0).hypot(p.1 - c.1))
+                    .fold(f64::INFINITY, f64::min);
+                spread(a).total_cmp(&spread(b))
+            })
+            .expect("at least one candidate");
+        centers.push(farthest);
+    }
+    let radius = (w * h * config.land_ratio / (config.continents as f64 * std::f64::consts::PI)).sqrt() * 1.3;
+
+    let mut heights = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (fx, fy) = (x as f64 / feature_size, y as f64 / feature_size);
+            // Warping the distance field gives coastlines bays and peninsulas
+            let (px, py) = (
+                x as f64 + (warp.fractal(fx, fy) - 0.5) * radius * 0.6,
+                y as f64 + (warp.fractal(fx + 31.7, fy + 17.3) - 0.5) * radius * 0.6,
+            );
+            let continent = centers.iter()
+                .map(|&(cx, cy)| (1.0 - ((px - cx).hypot(py - cy) / radius).powi(2)).max(0.0))
+                .fold(0.0, f64::max);
+            heights.push(continent * CONTINENT_WEIGHT + noise.fractal(fx * 2.0, fy * 2.0) * (1.0 - CONTINENT_WEIGHT));
+        }
+    }
+    heights
+}
+
+// Mountains follow ridged noise, favouring high ground
+fn add_mountains(terrain: &mut TerrainMap, heights: &[f64], sea_level: f64, rng: &mut GameRng) {
+    let (width, height) = (terrain.width(), terrain.height());
+    let ridges = Noise { seed: rng.gen() };
+    let feature_size = width.min(height) as f64 / 6.0;
+
+    let mut scores = vec![f64::NEG_INFINITY; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            if terrain.get(x, y) == Terrain::Water {
+                continue;
+            }
+            let ridge = 1.0 - (ridges.fractal(x as f64 / feature_size, y as f64 / feature_size) * 2.0 - 1.0).abs();
+            let elevation = (heights[i] - sea_level) / (1.0 - sea_level).max(f64::EPSILON);
+            scores[i] = ridge.powi(4) * (0.5 + elevation);
+        }
+    }
+
+    let land_scores: Vec<f64> = scores.iter().copied().filter(|s| s.is_finite()).collect();
+    let threshold = quantile(&land_scores, 1.0 - MOUNTAIN_SHARE);
+    for y in 0..height {
+        for x in 0..width {
+            if scores[y * width + x] > threshold {
+                terrain.set(x, y, Terrain::Mountain);
+            }
+        }
+    }
+}
+
+// Rivers start on high ground and run downhill to the sea, carving through pits
+fn add_rivers(terrain: &mut TerrainMap, heights: &[f64], sea_level: f64, rivers: usize, rng: &mut GameRng) {
+    let (width, height) = (terrain.width(), terrain.height());
+    let highlands: Vec<usize> = {
+        let land_heights: Vec<f64> = heights.iter().copied().filter(|&h| h > sea_level).collect();
+        let threshold = quantile(&land_heights, 0.8);
+        (0..heights.len()).filter(|&i| heights[i] > threshold).collect()
+    };
+    if highlands.is_empty() {
+        return;
+    }
+
+    for _ in 0..rivers {
+        let start = highlands[rng.gen_range(0..highlands.len())];
+        let (mut x, mut y) = (start % width, start / width);
+        let mut path = Vec::new();
+        let mut visited = HashSet::new();
+
+        while terrain.get(x, y) != Terrain::Water && path.len() < width + height {
+            path.push((x, y));
+            visited.insert((x, y));
+            let next = neighbors(x, y, width, height)
+                .filter(|cell| !visited.contains(cell))
+                .min_by(|a, b| heights[a.1 * width + a.0].total_cmp(&heights[b.1 * width + b.0]));
+            match next {
+                Some(cell) => (x, y) = cell,
+                None => break,
+            }
+        }
+
+        // Rivers that never reach the sea end in a lake at their last cell
+        for (step, &(rx, ry)) in path.iter().enumerate() {
+            if step % FORD_SPACING != FORD_SPACING - 1 {
+                terrain.set(rx, ry, Terrain::Water);
+            }
+        }
+    }
+}
+
+// Passable cells reachable from (x, y) within `radius` steps
+fn reach(terrain: &TerrainMap, x: usize, y: usize, radius: usize) -> usize {
+    let (width, height) = (terrain.width(), terrain.height());
+    let mut distance = HashMap::new();
+    let mut queue = VecDeque::from([(x, y)]);
+    distance.insert((x, y), 0);
+    while let Some((cx, cy)) = queue.pop_front() {
+        let d = distance[&(cx, cy)];
+        if d == radius {
+            continue;
+        }
+        for cell in neighbors(cx, cy, width, height) {
+            if terrain.is_passable(cell.0, cell.1) && !distance.contains_key(&cell) {
+                distance.insert(cell, d + 1);
+                queue.push_back(cell);
+            }
+        }
+    }
+    distance.len()
+}
+
+// Spawns are spread by farthest point sampling over random land cells that reach
+// enough land within a radius sized after each nation's share of the map. None
+// when too few cells fit even the loosest reach requirement, or they are too
+// close together for every nation to get its own.
+fn place_spawns(terrain: &TerrainMap, players: usize, rng: &mut GameRng) -> Option<Vec<(usize, usize)>> {
+    let (width, height) = (terrain.width(), terrain.height());
+    let land: Vec<(usize, usize)> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| terrain.get(x, y) == Terrain::Plains && spawn_fits(terrain, x, y))
+        .collect();
+    if players == 0 {
+        return Some(Vec::new());
+    }
+    if land.len() < players {
+        return None;
+    }
+
+    let share = land.len() / players;
+    let radius = ((share as f64).sqrt() / 2.0).clamp(4.0, 30.0) as usize;
+    let neighbourhood = 2 * radius * radius + 2 * radius + 1;
+    let mut sampled = HashSet::new();
+    let candidates: Vec<((usize, usize), usize)> = (0..SPAWN_CANDIDATES.min(land.len() * 2))
+        .map(|_| land[rng.gen_range(0..land.len())])
+        .filter(|&cell| sampled.insert(cell))
+        .map(|(x, y)| ((x, y), reach(terrain, x, y, radius)))
+        .collect();
+
+    for &required_share in &SPAWN_REACH_SHARES {
+        let required = (neighbourhood as f64 * required_share) as usize;
+        let eligible: Vec<(usize, usize)> = candidates.iter()
+            .filter(|&&(_, reach)| reach >= required)
+            .map(|&(cell, _)| cell)
+            .collect();
+        if eligible.len() < players {
+            continue;
+        }
+        if let Some(spawns) = spread_spawns(&eligible, players) {
+            return Some(spawns);
+        }
+    }
+    None
+}
+
+// Picks `players` cells one after the other, each the farthest from those already
+// picked among the cells that keep MIN_SPAWN_DISTANCE to all of them. The first
+// is the cell farthest from a random one, on the rim of the land, which leaves
+// the most room for the others.
+fn spread_spawns(eligible: &[(usize, usize)], players: usize) -> Option<Vec<(usize, usize)>> {
+    let (rx, ry) = eligible[0];
+    let first = eligible.iter()
+        .copied()
+        .max_by_key(|&(x, y)| x.abs_diff(rx).pow(2) + y.abs_diff(ry).pow(2))?;
+    let mut spawns = vec![first];
+    while spawns.len() < players {
+        let farthest = eligible.iter()
+            .copied()
+            .filter(|&(x, y)| {
+                spawns.iter().all(|&(sx, sy)| x.abs_diff(sx).max(y.abs_diff(sy)) >= MIN_SPAWN_DISTANCE)
+            })
+            .max_by_key(|&(x, y)| {
+                spawns.iter().map(|&(sx, sy)| x.abs_diff(sx).pow(2) + y.abs_diff(sy).pow(2)).min()
+            })?;
+        spawns.push(farthest);
+    }
+    Some(spawns)
+}
+
+// Starting territories are 3x3, keep them clear of the edge and of mountains
+fn spawn_fits(terrain: &TerrainMap, x: usize, y: usize) -> bool {
+    x > 0 && y > 0 && x + 1 < terrain.width() && y + 1 < terrain.height() &&
+        (y - 1..=y + 1).all(|ny| (x - 1..=x + 1).all(|nx| terrain.get(nx, ny) == Terrain::Plains))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::game::GameState;
+
+    fn config(land_ratio: f64, continents: usize) -> GameConfig {
+        GameConfig {
+            grid_width: 160,
+            grid_height: 120,
+            num_players: 8,
+            generate_map: true,
+            land_ratio,
+            continents,
+            ..GameConfig::default()
+        }
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let first = generate(&config(0.4, 3), 12).unwrap();
+        let second = generate(&config(0.4, 3), 12).unwrap();
+        assert_eq!(first.terrain, second.terrain);
+        assert_eq!(first.spawns, second.spawns);
+        assert_ne!(generate(&config(0.4, 3), 13).unwrap().terrain, first.terrain);
+    }
+
+    #[test]
+    fn land_ratio_is_respected() {
+        for land_ratio in [0.25, 0.5, 0.75] {
+            let map = generate(&config(land_ratio, 2), 3).unwrap();
+            let rows = map.terrain.rows();
+            let land = rows.iter().flat_map(|row| row.chars()).filter(|&c| c != '~').count();
+            let ratio = land as f64 / (160 * 120) as f64;
+            // Rivers take a little land away
+            assert!((ratio - land_ratio).abs() < 0.05, "asked for {} land, got {}", land_ratio, ratio);
+        }
+    }
+
+    #[test]
+    fn every_nation_gets_reachable_land() {
+        let map = generate(&config(0.35, 4), 5).unwrap();
+        assert_eq!(map.spawns.len(), 8);
+        for &(x, y) in &map.spawns {
+            assert!(spawn_fits(&map.terrain, x, y));
+            assert!(reach(&map.terrain, x, y, 10) > 100, "spawn at ({}, {}) is cramped", x, y);
+            let close = map.spawns.iter().filter(|&&(sx, sy)| x.abs_diff(sx).max(y.abs_diff(sy)) < MIN_SPAWN_DISTANCE);
+            assert_eq!(close.count(), 1, "spawn at ({}, {}) has a neighbour", x, y);
+        }
+    }
+
+    #[test]
+    fn cramped_land_keeps_spawns_apart() {
+        // 15 cells fit a 3x3 start on a 7x5 plain, but only two of them 3 apart
+        let terrain = TerrainMap::new(7, 5, Terrain::Plains);
+        for seed in 0..20 {
+            let mut rng = GameRng::seed_from_u64(seed);
+            let spawns = place_spawns(&terrain, 2, &mut rng).expect("two nations fit");
+            let ((ax, ay), (bx, by)) = (spawns[0], spawns[1]);
+            assert!(ax.abs_diff(bx).max(ay.abs_diff(by)) >= MIN_SPAWN_DISTANCE, "{:?} overlap", spawns);
+            assert_eq!(place_spawns(&terrain, 3, &mut rng), None);
+        }
+    }
+
+    #[test]
+    fn crowded_maps_are_refused() {
+        let crowded = GameConfig { grid_width: 60, grid_height: 40, num_players: 40, ..config(0.06, 1) };
+        match generate(&crowded, 5) {
+            Err(MapError::Invalid(reason)) => assert!(reason.contains("no room for 40 spawn points"), "{}", reason),
+            other => panic!("expected an invalid map, got {:?}", other.map(|map| map.spawns.len())),
+        }
+        assert!(GameState::from_config(GameConfig { seed: Some(5), ..crowded }).is_err());
+    }
+}
@@ -1,4 +1,10 @@
 pub mod config;
 pub mod types;
+pub mod terrain;
+pub mod map;
+pub mod mapgen;
 pub mod game;
 pub mod timing;
+pub mod logging;
+pub mod simulation;
+pub mod strategy;
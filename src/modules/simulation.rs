@@ -0,0 +1,136 @@
+use serde::Serialize;
+use crate::modules::config::GameConfig;
+use crate::modules::game::{GameState, VictoryReason};
+use crate::modules::map::MapError;
+use crate::modules::types::Elimination;
+
+#[derive(Clone, Copy, Serialize, Debug)]
+pub struct PlayerArea {
+    pub player: usize,
+    pub area: i32,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct SimulationSummary {
+    pub seed: u64,
+    pub ticks: u64,
+    pub finished: bool,
+    pub winner: Option<usize>,
+    pub winning_team: Option<usize>,
+    pub victory: Option<VictoryReason>,
+    // Largest nation when the tick limit was hit, equal to the winner otherwise
+    pub leader: Option<usize>,
+    pub leader_start: Option<(usize, usize)>,
+    pub eliminations: Vec<Elimination>,
+    pub final_areas: Vec<PlayerArea>,
+}
+
+impl SimulationSummary {
+    pub fn from_state(state: &GameState) -> Self {
+        let mut final_areas: Vec<PlayerArea> = state.players.iter()
+            .map(|p| PlayerArea { player: p.id, area: p.area })
+            .collect();
+        final_areas.sort_by(|a, b| b.area.cmp(&a.area).then(a.player.cmp(&b.player)));
+
+        let leader = final_areas.first().map(|a| a.player);
+        let leader_start = leader
+            .and_then(|id| state.players.iter().find(|p| p.id == id))
+            .map(|p| (p.x, p.y));
+
+        SimulationSummary {
+            seed: state.seed,
+            ticks: state.tick,
+            finished: state.is_finished(),
+            winner: state.winner(),
+            winning_team: state.winning_team(),
+            victory: state.victory().map(|(reason, _)| reason),
+            leader,
+            leader_start,
+            eliminations: state.eliminations.clone(),
+            final_areas,
+        }
+    }
+}
+
+// Runs a full game without any timing, until one nation remains or `max_ticks` is reached
+pub fn run_game(config: GameConfig, max_ticks: u64) -> Result<SimulationSummary, MapError> {
+    run_game_with(config, max_ticks, |_| {})
+}
+
+// Same as run_game, calling `observe` after every tick to collect metrics along the way
+pub fn run_game_with<F: FnMut(&GameState)>(
+    config: GameConfig,
+    max_ticks: u64,
+    observe: F,
+) -> Result<SimulationSummary, MapError> {
+    let mut state = GameState::from_config(config)?;
+    state.initialize_players();
+    play_out(&mut state, max_ticks, observe);
+    Ok(SimulationSummary::from_state(&state))
+}
+
+// Advances an existing game (fresh or restored from a snapshot) until it ends or reaches `max_ticks`
+pub fn play_out<F: FnMut(&GameState)>(state: &mut GameState, max_ticks: u64, mut observe: F) {
+    while !state.is_finished() && state.tick < max_ticks {
+        state.update();
+        observe(state);
+    }
+}
+
+// Gini coefficient of the area distribution, eliminated nations count as zero area
+pub fn area_gini(state: &GameState) -> f64 {
+    let mut areas = vec![0i64; state.config.num_players];
+    for player in &state.players {
+        if player.id < areas.len() {
+            areas[player.id] = player.area.max(0) as i64;
+        }
+    }
+    gini(&mut areas)
+}
+
+pub fn gini(values: &mut [i64]) -> f64 {
+    let total: i64 = values.iter().sum();
+    if values.is_empty() || total == 0 {
+        return 0.0;
+    }
+
+    values.sort_unstable();
+    let n = values.len() as f64;
+    let weighted: f64 = values.iter()
+        .enumerate()
+        .map(|(i, &v)| (i as f64 + 1.0) * v as f64)
+        .sum();
+    (2.0 * weighted) / (n * total as f64) - (n + 1.0) / n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gini_measures_how_unevenly_land_is_held() {
+        assert_eq!(gini(&mut [25, 25, 25, 25]), 0.0);
+        assert!((gini(&mut [0, 0, 0, 100]) - 0.75).abs() < 1e-9);
+        assert_eq!(gini(&mut []), 0.0);
+        assert_eq!(gini(&mut [0, 0]), 0.0);
+
+        // Eliminated nations count as holding nothing
+        let config = GameConfig { grid_width: 40, grid_height: 30, num_players: 4, ..GameConfig::default() };
+        let mut state = GameState::new(config.clone());
+        state.players.push(crate::modules::types::Player::new(2, 5, 5, &config));
+        state.players[0].area = 50;
+        assert!((area_gini(&state) - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn same_seed_plays_the_same_game() {
+        let config = GameConfig { grid_width: 60, grid_height: 40, num_players: 3, seed: Some(12), ..GameConfig::default() };
+        let first = run_game(config.clone(), 300).unwrap();
+        let second = run_game(config.clone(), 300).unwrap();
+        assert_eq!(serde_json::to_string(&first).unwrap(), serde_json::to_string(&second).unwrap());
+        assert!(first.ticks <= 300);
+
+        let other = run_game(GameConfig { seed: Some(13), ..config }, 300).unwrap();
+        assert_ne!(serde_json::to_string(&first).unwrap(), serde_json::to_string(&other).unwrap());
+    }
+}
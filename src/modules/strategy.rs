@@ -0,0 +1,491 @@
+use std::cell::OnceCell;
+use std::fmt;
+use std::str::FromStr;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::modules::config::GameConfig;
+use crate::modules::game::{GameState, Landing, SeaRoutes, Treaty};
+use crate::modules::types::{GameRng, Player};
+
+// An attack a strategy wants to launch this tick, `target` is None for empty land.
+// Orders with a `landing` go by boat to that coastal cell instead of over the border,
+// `toward` aims a land attack at a cell rather than the whole shared border.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AttackOrder {
+    pub target: Option<usize>,
+    pub investment: i32,
+    pub landing: Option<(usize, usize)>,
+    pub toward: Option<(usize, usize)>,
+}
+
+impl AttackOrder {
+    pub fn naval(landing: &Landing, investment: i32) -> Self {
+        AttackOrder { target: landing.owner, investment, landing: Some((landing.x, landing.y)), toward: None }
+    }
+}
+
+// Diplomatic moves a strategy can make, carried out before its attacks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiplomaticAction {
+    Propose { to: usize, treaty: Treaty },
+    Accept { from: usize },
+    Break { with: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NeighborView {
+    pub id: usize,
+    // Cell edges shared with the player's territory
+    pub contacts: u32,
+    // Resources the neighbour could defend with
+    pub strength: i32,
+    pub area: i32,
+    // Where the nation started out, bots push for it to split its territory
+    pub capital: (usize, usize),
+}
+
+// Read-only view of the game from one player's side. The border is only scanned
+// when a strategy first asks for it, most ticks most bots decide to wait.
+pub struct PlayerView<'a> {
+    state: &'a GameState,
+    player: &'a Player,
+    border: OnceCell<(Vec<NeighborView>, bool)>,
+    sea: OnceCell<SeaRoutes>,
+}
+
+impl<'a> PlayerView<'a> {
+    pub fn new(state: &'a GameState, player: &'a Player) -> Self {
+        PlayerView { state, player, border: OnceCell::new(), sea: OnceCell::new() }
+    }
+
+    pub fn id(&self) -> usize {
+        self.player.id
+    }
+
+    pub fn resources(&self) -> i32 {
+        self.player.resources
+    }
+
+    pub fn max_resources(&self) -> i32 {
+        self.player.max_resources(self.config())
+    }
+
+    // Share of the resource cap currently held, interest dries up as it nears 1
+    pub fn resource_ratio(&self) -> f64 {
+        self.resources() as f64 / self.max_resources() as f64
+    }
+
+    pub fn area(&self) -> i32 {
+        self.player.area
+    }
+
+    pub fn config(&self) -> &GameConfig {
+        &self.state.config
+    }
+
+    // Bordering nations it is free to attack, ordered by id. Teammates, allies and
+    // pact partners are left out.
+    pub fn neighbors(&self) -> &[NeighborView] {
+        &self.border().0
+    }
+
+    pub fn treaty_with(&self, other: usize) -> Option<Treaty> {
+        self.state.diplomacy.treaty(self.player.id, other)
+    }
+
+    // Offers waiting for this player's answer, as (proposer, treaty, proposer's resources)
+    pub fn offers(&self) -> Vec<(usize, Treaty, i32)> {
+        self.state.diplomacy.proposals_to(self.player.id)
+            .filter_map(|p| {
+                let proposer = self.state.players.iter().find(|player| player.id == p.from)?;
+                Some((p.from, p.treaty, proposer.resources))
+            })
+            .collect()
+    }
+
+    pub fn has_pending_offer(&self) -> bool {
+        self.state.diplomacy.proposals.iter().any(|p| p.from == self.player.id)
+    }
+
+    // Nations it has a treaty with, with their resources
+    pub fn partners(&self) -> Vec<(usize, Treaty, i32)> {
+        self.state.players.iter()
+            .filter_map(|p| Some((p.id, self.treaty_with(p.id)?, p.resources)))
+            .collect()
+    }
+
+    pub fn borders_empty_land(&self) -> bool {
+        self.border().1
+    }
+
+    // Shores a boat from the player's coast can reach, only searched when asked for
+    pub fn landings(&self) -> &[Landing] {
+        self.sea.get_or_init(|| self.state.sea_routes(self.player.id)).landings()
+    }
+
+    // Landings a boat carrying `investment` reaches with something left to land,
+    // nearest first
+    pub fn affordable_landings(&self, investment: i32) -> Vec<Landing> {
+        let cost_per_cell = self.config().naval_cost_per_cell;
+        let mut landings: Vec<Landing> = self.landings().iter()
+            .filter(|landing| (landing.distance as i32 * cost_per_cell) < investment)
+            .filter(|landing| landing.owner.is_none_or(|owner| !self.state.is_friendly(self.player.id, owner)))
+            .copied()
+            .collect();
+        landings.sort_by_key(|landing| landing.distance);
+        landings
+    }
+
+    // Strategies are free to ask for anything, only targets on the border or on a
+    // reachable shore are executed
+    pub fn is_valid(&self, order: &AttackOrder) -> bool {
+        if order.investment <= 0 {
+            return false;
+        }
+        if let Some((x, y)) = order.landing {
+            return self.affordable_landings(order.investment).iter()
+                .any(|landing| (landing.x, landing.y) == (x, y) && landing.owner == order.target);
+        }
+        match order.target {
+            Some(target) => self.neighbors().iter().any(|n| n.id == target),
+            None => self.borders_empty_land(),
+        }
+    }
+
+    // Never spends below the configured minimum cost, nor more than the player has
+    pub fn investment(&self, ratio: f64) -> i32 {
+        let base_investment = (self.resources() as f64 * ratio) as i32;
+        std::cmp::max(base_investment, self.config().min_expansion_cost).min(self.resources())
+    }
+
+    fn border(&self) -> &(Vec<NeighborView>, bool) {
+        self.border.get_or_init(|| {
+            let (contacts, has_empty_space) = self.state.border_neighbors(self.player.id);
+            let neighbors = contacts.into_iter()
+                .filter(|&(id, _)| !self.state.is_friendly(self.player.id, id))
+                .filter_map(|(id, contacts)| {
+                    let neighbor = self.state.players.iter().find(|p| p.id == id)?;
+                    Some(NeighborView {
+                        id,
+                        contacts,
+                        strength: neighbor.resources,
+                        area: neighbor.area,
+                        capital: (neighbor.x, neighbor.y),
+                    })
+                })
+                .collect();
+            (neighbors, has_empty_space)
+        })
+    }
+}
+
+pub trait Strategy: Send + Sync {
+    fn decide(&self, view: &PlayerView, rng: &mut GameRng) -> Vec<AttackOrder>;
+
+    // By default a bot takes any treaty offered by a nation at least as strong as
+    // itself, and now and then asks a neighbour twice its strength for a pact
+    fn diplomacy(&self, view: &PlayerView, rng: &mut GameRng) -> Vec<DiplomaticAction> {
+        let mut actions: Vec<DiplomaticAction> = view.offers().into_iter()
+            .filter(|&(_, _, strength)| strength >= view.resources())
+            .map(|(from, _, _)| DiplomaticAction::Accept { from })
+            .collect();
+        if actions.is_empty() && !view.has_pending_offer() && rng.gen_bool(DIPLOMACY_CHANCE) {
+            let threat = view.neighbors().iter()
+                .filter(|n| n.strength > view.resources() * 2)
+                .max_by_key(|n| (n.strength, std::cmp::Reverse(n.id)));
+            if let Some(threat) = threat {
+                actions.push(DiplomaticAction::Propose { to: threat.id, treaty: Treaty::Pact });
+            }
+        }
+        actions
+    }
+}
+
+// Chance per tick that a bot looks for a pact, keeps proposals from flooding clients
+const DIPLOMACY_CHANCE: f64 = 0.02;
+
+// The original bot: expands with a probability that grows with its reserves, prefers
+// empty land and otherwise picks a neighbour weighted by contact length
+pub struct RandomStrategy;
+
+impl Strategy for RandomStrategy {
+    fn decide(&self, view: &PlayerView, rng: &mut GameRng) -> Vec<AttackOrder> {
+        let expansion_chance = view.config().base_expansion_chance;
+        if !rng.gen_bool((expansion_chance * view.resource_ratio()).clamp(0.0, 1.0)) {
+            return Vec::new();
+        }
+        let investment = view.investment(rng.gen_range(0.2..0.4));
+        if investment <= 0 {
+            return Vec::new();
+        }
+
+        let neighbors = view.neighbors();
+        if view.borders_empty_land() && (neighbors.is_empty() || rng.gen_bool(expansion_chance)) {
+            return vec![AttackOrder { target: None, investment, landing: None, toward: None }];
+        }
+        if neighbors.is_empty() {
+            // Nothing left to take on its own shores, try its luck overseas
+            let landings = view.affordable_landings(investment);
+            if landings.is_empty() {
+                return Vec::new();
+            }
+            let landing = &landings[rng.gen_range(0..landings.len())];
+            return vec![AttackOrder::naval(landing, investment)];
+        }
+
+        // Weight neighbors by their frequency of occurrence
+        let total_weight: u32 = neighbors.iter().map(|n| n.contacts).sum();
+        let mut choice = rng.gen_range(0..total_weight);
+        for neighbor in neighbors {
+            if choice < neighbor.contacts {
+                return vec![AttackOrder { target: Some(neighbor.id), investment, landing: None, toward: None }];
+            }
+            choice -= neighbor.contacts;
+        }
+        Vec::new()
+    }
+}
+
+// Attacks every tick it holds a quarter of its cap, going for the longest front
+// with a nation it outguns before bothering with empty land
+pub struct AggressiveStrategy;
+
+impl Strategy for AggressiveStrategy {
+    fn decide(&self, view: &PlayerView, _rng: &mut GameRng) -> Vec<AttackOrder> {
+        if view.resource_ratio() < 0.25 {
+            return Vec::new();
+        }
+        let investment = view.investment(0.6);
+        let longest_front = |weaker_only: bool| view.neighbors().iter()
+            .filter(|n| !weaker_only || n.strength < investment)
+            .max_by_key(|n| (n.contacts, std::cmp::Reverse(n.id)));
+
+        // Nations are driven straight at their capital
+        let (target, toward) = if let Some(n) = longest_front(true) {
+            (Some(n.id), Some(n.capital))
+        } else if view.borders_empty_land() {
+            (None, None)
+        } else if let Some(n) = longest_front(false) {
+            (Some(n.id), Some(n.capital))
+        } else {
+            // Landlocked by water, invade the nearest shore
+            return view.affordable_landings(investment).first()
+                .map(|landing| vec![AttackOrder::naval(landing, investment)])
+                .unwrap_or_default();
+        };
+        vec![AttackOrder { target, investment, landing: None, toward }]
+    }
+
+    // Never signs anything, and tears up pacts with partners it could overrun
+    fn diplomacy(&self, view: &PlayerView, _rng: &mut GameRng) -> Vec<DiplomaticAction> {
+        view.partners().into_iter()
+            .filter(|&(_, treaty, strength)| treaty == Treaty::Pact && strength * 2 < view.resources())
+            .map(|(with, _, _)| DiplomaticAction::Break { with })
+            .collect()
+    }
+}
+
+// Saves up until interest starts to dry up near the cap, then grows into empty
+// land and only picks fights with nations far weaker than itself
+pub struct EconomicStrategy;
+
+impl Strategy for EconomicStrategy {
+    fn decide(&self, view: &PlayerView, _rng: &mut GameRng) -> Vec<AttackOrder> {
+        if view.resource_ratio() < 0.8 {
+            return Vec::new();
+        }
+        let investment = view.investment(0.3);
+        if view.borders_empty_land() {
+            return vec![AttackOrder { target: None, investment, landing: None, toward: None }];
+        }
+        if let Some(weak) = view.neighbors().iter()
+            .filter(|n| n.strength * 2 < view.resources())
+            .min_by_key(|n| (n.strength, n.id))
+        {
+            return vec![AttackOrder { target: Some(weak.id), investment, landing: None, toward: None }];
+        }
+        // Only sails for unclaimed shores
+        view.affordable_landings(investment).iter()
+            .find(|landing| landing.owner.is_none())
+            .map(|landing| vec![AttackOrder::naval(landing, investment)])
+            .unwrap_or_default()
+    }
+}
+
+// Hunts the weakest neighbour whenever it can outspend it, pushing for its
+// capital, and expands into empty land in the meantime
+pub struct WeakestNeighborStrategy;
+
+impl Strategy for WeakestNeighborStrategy {
+    fn decide(&self, view: &PlayerView, _rng: &mut GameRng) -> Vec<AttackOrder> {
+        if view.resource_ratio() < 0.4 {
+            return Vec::new();
+        }
+        let weakest = view.neighbors().iter()
+            .min_by_key(|n| (n.strength, std::cmp::Reverse(n.contacts), n.id));
+        match weakest {
+            Some(weakest) if weakest.strength < view.investment(0.5) => {
+                let investment = view.investment(0.5);
+                vec![AttackOrder { target: Some(weakest.id), investment, landing: None, toward: Some(weakest.capital) }]
+            }
+            _ if view.borders_empty_land() => vec![AttackOrder { target: None, investment: view.investment(0.3), landing: None, toward: None }],
+            None => {
+                // Cut off by water, land on the weakest nation within reach
+                let investment = view.investment(0.5);
+                let strength = |owner: Option<usize>| owner
+                    .and_then(|id| view.state.players.iter().find(|p| p.id == id))
+                    .map_or(0, |p| p.resources);
+                view.affordable_landings(investment).iter()
+                    .min_by_key(|landing| (strength(landing.owner), landing.distance))
+                    .map(|landing| vec![AttackOrder::naval(landing, investment)])
+                    .unwrap_or_default()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyKind {
+    #[default]
+    Random,
+    Aggressive,
+    Economic,
+    WeakestNeighbor,
+}
+
+impl StrategyKind {
+    pub const ALL: [StrategyKind; 4] = [
+        StrategyKind::Random,
+        StrategyKind::Aggressive,
+        StrategyKind::Economic,
+        StrategyKind::WeakestNeighbor,
+    ];
+
+    pub fn strategy(self) -> &'static dyn Strategy {
+        match self {
+            StrategyKind::Random => &RandomStrategy,
+            StrategyKind::Aggressive => &AggressiveStrategy,
+            StrategyKind::Economic => &EconomicStrategy,
+            StrategyKind::WeakestNeighbor => &WeakestNeighborStrategy,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StrategyKind::Random => "random",
+            StrategyKind::Aggressive => "aggressive",
+            StrategyKind::Economic => "economic",
+            StrategyKind::WeakestNeighbor => "weakest_neighbor",
+        }
+    }
+}
+
+impl fmt::Display for StrategyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for StrategyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StrategyKind::ALL.into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| format!("unknown strategy '{}'", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use crate::modules::terrain::{Terrain, TerrainMap};
+
+    // Player 0 holds the left half, player 1 (strong) the top right quarter and
+    // player 2 (weak) the bottom right quarter, so nobody borders empty land
+    fn contested_state() -> GameState {
+        let config = GameConfig { grid_width: 20, grid_height: 10, num_players: 3, seed: Some(1), ..GameConfig::default() };
+        let mut state = GameState::new(config.clone());
+        for (id, resources) in [(0, 4500), (1, 1500), (2, 100)] {
+            let mut player = Player::new(id, 0, 0, &config);
+            player.resources = resources;
+            state.players.push(player);
+        }
+        for (y, row) in state.grid.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                *cell = Some(if x < 10 { 0 } else if y < 3 { 1 } else { 2 });
+            }
+        }
+        for player in &mut state.players {
+            player.area = state.grid.iter().flatten().filter(|&&cell| cell == Some(player.id)).count() as i32;
+        }
+        state
+    }
+
+    fn targets(kind: StrategyKind, state: &GameState) -> Vec<Option<usize>> {
+        let view = PlayerView::new(state, &state.players[0]);
+        let mut rng = GameRng::seed_from_u64(0);
+        let orders = kind.strategy().decide(&view, &mut rng);
+        assert!(orders.iter().all(|order| view.is_valid(order)));
+        orders.into_iter().map(|order| order.target).collect()
+    }
+
+    #[test]
+    fn view_reports_border_and_strength() {
+        let state = contested_state();
+        let view = PlayerView::new(&state, &state.players[0]);
+        assert!(!view.borders_empty_land());
+        let neighbors: Vec<_> = view.neighbors().iter().map(|n| (n.id, n.contacts, n.strength)).collect();
+        assert_eq!(neighbors, vec![(1, 3, 1500), (2, 7, 100)]);
+    }
+
+    #[test]
+    fn strategies_pick_their_targets() {
+        let state = contested_state();
+        assert_eq!(targets(StrategyKind::WeakestNeighbor, &state), vec![Some(2)]);
+        assert_eq!(targets(StrategyKind::Aggressive, &state), vec![Some(2)]);
+        assert_eq!(targets(StrategyKind::Economic, &state), vec![Some(2)]);
+
+        let mut hoarding = contested_state();
+        hoarding.players[0].resources = 3000;
+        assert_eq!(targets(StrategyKind::Economic, &hoarding), vec![]);
+    }
+
+    #[test]
+    fn landlocked_bots_sail() {
+        // Player 0 fills the left island, only the right one across the strait is left
+        let mut state = contested_state();
+        state.players.truncate(1);
+        let mut terrain = TerrainMap::new(20, 10, Terrain::Plains);
+        for y in 0..10 {
+            for x in 10..14 {
+                terrain.set(x, y, Terrain::Water);
+            }
+        }
+        state.set_terrain(terrain).unwrap();
+        for row in state.grid.iter_mut() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                *cell = if x < 10 { Some(0) } else { None };
+            }
+        }
+
+        let view = PlayerView::new(&state, &state.players[0]);
+        let mut rng = GameRng::seed_from_u64(0);
+        let orders = StrategyKind::Aggressive.strategy().decide(&view, &mut rng);
+        assert_eq!(orders.len(), 1);
+        assert!(view.is_valid(&orders[0]));
+        let (x, _) = orders[0].landing.expect("a naval order");
+        assert_eq!((x, orders[0].target), (14, None));
+    }
+
+    #[test]
+    fn parses_names() {
+        for kind in StrategyKind::ALL {
+            assert_eq!(kind.name().parse::<StrategyKind>(), Ok(kind));
+        }
+        assert!("passive".parse::<StrategyKind>().is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::modules::config::GameConfig;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Terrain {
+    #[default]
+    Plains,
+    // Can never be captured, nations have to go around it
+    Water,
+    // Costs `mountain_cost` investment per captured pixel instead of 1
+    Mountain,
+}
+
+impl Terrain {
+    pub const ALL: [Terrain; 3] = [Terrain::Plains, Terrain::Water, Terrain::Mountain];
+
+    #[inline]
+    pub fn is_passable(self) -> bool {
+        self != Terrain::Water
+    }
+
+    #[inline]
+    pub fn capture_cost(self, config: &GameConfig) -> i32 {
+        match self {
+            Terrain::Mountain => config.mountain_cost,
+            _ => 1,
+        }
+    }
+
+    // One character per cell in serialized maps and on the wire
+    pub fn symbol(self) -> char {
+        match self {
+            Terrain::Plains => '.',
+            Terrain::Water => '~',
+            Terrain::Mountain => '^',
+        }
+    }
+
+    pub fn from_symbol(symbol: char) -> Option<Self> {
+        Terrain::ALL.into_iter().find(|terrain| terrain.symbol() == symbol)
+    }
+}
+
+// Terrain of every cell, stored next to the owner grid. Serialized as one string
+// of terrain symbols per row, which keeps snapshots and replays readable.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(into = "Vec<String>", try_from = "Vec<String>")]
+pub struct TerrainMap {
+    width: usize,
+    height: usize,
+    cells: Vec<Terrain>,
+}
+
+#[derive(Debug)]
+pub struct TerrainError(String);
+
+impl fmt::Display for TerrainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid terrain: {}", self.0)
+    }
+}
+
+impl std::error::Error for TerrainError {}
+
+impl TerrainError {
+    pub fn new(reason: String) -> Self {
+        TerrainError(reason)
+    }
+}
+
+impl TerrainMap {
+    pub fn new(width: usize, height: usize, terrain: Terrain) -> Self {
+        TerrainMap { width, height, cells: vec![terrain; width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> Terrain {
+        self.cells[y * self.width + x]
+    }
+
+    #[inline]
+    pub fn set(&mut self, x: usize, y: usize, terrain: Terrain) {
+        self.cells[y * self.width + x] = terrain;
+    }
+
+    #[inline]
+    pub fn is_passable(&self, x: usize, y: usize) -> bool {
+        self.get(x, y).is_passable()
+    }
+
+    // Cells a nation can own
+    pub fn passable_cells(&self) -> usize {
+        self.cells.iter().filter(|terrain| terrain.is_passable()).count()
+    }
+
+    pub fn rows(&self) -> Vec<String> {
+        self.cells.chunks(self.width.max(1))
+            .map(|row| row.iter().map(|terrain| terrain.symbol()).collect())
+            .collect()
+    }
+
+    pub fn from_rows<S: AsRef<str>>(rows: &[S]) -> Result<Self, TerrainError> {
+        let width = rows.first().map_or(0, |row| row.as_ref().chars().count());
+        let mut cells = Vec::with_capacity(width * rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            let start = cells.len();
+            for symbol in row.as_ref().chars() {
+                let terrain = Terrain::from_symbol(symbol)
+                    .ok_or_else(|| TerrainError(format!("unknown symbol '{}' in row {}", symbol, y)))?;
+                cells.push(terrain);
+            }
+            if cells.len() - start != width {
+                return Err(TerrainError(format!("row {} is not {} cells wide", y, width)));
+            }
+        }
+        Ok(TerrainMap { width, height: rows.len(), cells })
+    }
+}
+
+impl From<TerrainMap> for Vec<String> {
+    fn from(map: TerrainMap) -> Self {
+        map.rows()
+    }
+}
+
+impl TryFrom<Vec<String>> for TerrainMap {
+    type Error = TerrainError;
+
+    fn try_from(rows: Vec<String>) -> Result<Self, Self::Error> {
+        TerrainMap::from_rows(&rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_round_trip() {
+        let rows = ["..~~^", ".^~..", "~~~.."];
+        let map = TerrainMap::from_rows(&rows).unwrap();
+        assert_eq!((map.width(), map.height()), (5, 3));
+        assert_eq!(map.get(4, 0), Terrain::Mountain);
+        assert!(!map.is_passable(2, 1));
+
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"["..~~^",".^~..","~~~.."]"#);
+        assert_eq!(serde_json::from_str::<TerrainMap>(&json).unwrap(), map);
+
+        assert!(TerrainMap::from_rows(&["..", "..."]).is_err());
+        assert!(TerrainMap::from_rows(&[".x"]).is_err());
+    }
+}
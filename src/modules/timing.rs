@@ -56,11 +56,15 @@ impl ExecutionTimer {
 
 impl Drop for ExecutionTimer {
     fn drop(&mut self) {
+        // Timers also run outside the server (tests, headless runs), skip recording there
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
         let duration = self.start.elapsed();
         let name = self.name.clone();
         let stats = self.stats.clone();
         
-        tokio::spawn(async move {
+        handle.spawn(async move {
             let mut guard = stats.write().await;
             guard.record_execution(&name, duration);
         });
@@ -1,12 +1,29 @@
 use std::collections::HashSet;
-use rand::Rng;
-use serde::Serialize;
-use crate::modules::config::*;
+use serde::{Deserialize, Serialize};
+use crate::modules::config::GameConfig;
+use crate::modules::terrain::TerrainMap;
 
 pub type Grid = Vec<Vec<Option<usize>>>;
 pub type Players = Vec<Player>;
+pub type GameRng = rand_chacha::ChaCha8Rng;
 
-#[derive(Clone, Copy, Serialize, Debug)]
+// Colours of nations playing alone, by id
+const PALETTE: [&str; 20] = [
+    "#ff0000", "#00ff00", "#0000ff", "#ffff00", "#ff00ff", "#00ffff",
+    "#ff8000", "#ff0080", "#80ff00", "#00ff80", "#8000ff", "#0080ff",
+    "#ff8080", "#80ff80", "#8080ff", "#ffff80", "#ff80ff", "#80ffff",
+    "#ff8040", "#40ff40",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerKind {
+    #[default]
+    Bot,
+    Human,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Player {
     pub id: usize,
     pub x: usize,
@@ -14,64 +31,140 @@ pub struct Player {
     pub resources: i32,
     pub area: i32,
     pub base_interest_rate: f64,
+    // Cells cut off from the nation's core, they produce no income while
+    // fragment decay is on
+    #[serde(default)]
+    pub cut_off: i32,
+    // Team from the configuration, None for nations playing alone
+    #[serde(default)]
+    pub team: Option<usize>,
+    // Identity shown to every client. Bots get a numbered name and a colour
+    // from the palette or their team, players from the lobby bring their own.
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub color: String,
+    // Follows the claims, which are not part of snapshots either
+    #[serde(skip)]
+    pub kind: PlayerKind,
 }
 
 impl Player {
-    pub fn new(id: usize, x: usize, y: usize) -> Self {
-        println!("Creating new player {} at position ({}, {})", id, x, y);
+    pub fn new(id: usize, x: usize, y: usize, config: &GameConfig) -> Self {
+        crate::game_log!("Creating new player {} at position ({}, {})", id, x, y);
         Player {
             id,
             x,
             y,
-            resources: 1000,
+            resources: config.starting_resources,
             area: 1,
-            base_interest_rate: BASE_INTEREST_RATE,
+            base_interest_rate: config.base_interest_rate,
+            cut_off: 0,
+            team: config.team_for(id),
+            name: format!("Nation {}", id),
+            color: default_color(id, config),
+            kind: PlayerKind::Bot,
         }
     }
 
     #[inline]
-    pub fn max_resources(&self) -> i32 {
-        std::cmp::max(self.area * MAX_RESOURCES_MULTIPLIER, 2000)
+    pub fn max_resources(&self, config: &GameConfig) -> i32 {
+        std::cmp::max(self.area * config.max_resources_multiplier, 2000)
     }
 
     #[inline]
-    pub fn interest_rate(&self) -> f64 {
-        let resource_ratio = self.resources as f64 / self.max_resources() as f64;
+    pub fn interest_rate(&self, config: &GameConfig) -> f64 {
+        let resource_ratio = self.resources as f64 / self.max_resources(config) as f64;
         let resource_factor = f64::max(1.0 - resource_ratio.powi(2), 0.0);
         self.base_interest_rate * resource_factor
     }
 
-    pub fn update_resources(&mut self) {
-        let territory_resources = self.area;
-        let interest = (self.resources as f64 * self.interest_rate()) as i32;
-        let total_gain = territory_resources + interest;        
+    // Extra investment an attacker spends per pixel taken from this player, grows
+    // with the resources it holds per pixel of territory
+    #[inline]
+    pub fn defense_cost(&self, config: &GameConfig) -> i32 {
+        if self.area <= 0 || self.resources <= 0 {
+            return 0;
+        }
+        let density = self.resources as f64 / self.area as f64;
+        ((density * config.defense_factor) as i32).min(config.max_defense_cost)
+    }
+
+    // What the nation earns per tick from its connected land and interest,
+    // before the resource cap
+    pub fn income(&self, config: &GameConfig) -> i32 {
+        let territory_resources = self.area - self.cut_off;
+        let interest = (self.resources as f64 * self.interest_rate(config)) as i32;
+        territory_resources + interest
+    }
+
+    pub fn update_resources(&mut self, config: &GameConfig) {
+        let total_gain = self.income(config);
         self.resources = std::cmp::min(
             self.resources + total_gain,
-            self.max_resources()
+            self.max_resources(config)
         );
     }
+}
 
-    pub fn try_expand(&self) -> bool {
-        let resource_ratio = self.resources as f64 / self.max_resources() as f64;
-        let expansion_chance = BASE_EXPANSION_CHANCE * resource_ratio;
-        let will_expand = rand::thread_rng().gen_bool(expansion_chance);
-        
-        
-        will_expand
-    }
+// Teams get hues spread around the wheel and their members shades of it,
+// nations playing alone the palette colour of their id
+pub fn default_color(id: usize, config: &GameConfig) -> String {
+    let Some(team) = config.team_for(id) else {
+        return PALETTE[id % PALETTE.len()].to_string();
+    };
+    let mut teams: Vec<usize> = config.teams.clone();
+    teams.sort_unstable();
+    teams.dedup();
+    let hue = teams.iter().position(|&t| t == team).unwrap_or(0) as f64 * 360.0 / teams.len() as f64;
+    let rank = config.teams[..id].iter().filter(|&&t| t == team).count();
+    let lightness = (40 + (rank * 12) % 40) as f64 / 100.0;
+    hsl_to_hex(hue, 0.9, lightness)
+}
 
-    pub fn calculate_expansion_investment(&self) -> i32 {
-        let mut rng = rand::thread_rng();
-        let investment_ratio = rng.gen_range(0.2..0.4);
-        let base_investment = (self.resources as f64 * investment_ratio) as i32;
-        let investment = std::cmp::max(base_investment, MIN_EXPANSION_COST);
-        let final_investment = std::cmp::min(investment, self.resources);
-        
-        final_investment
+fn hsl_to_hex(hue: f64, saturation: f64, lightness: f64) -> String {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let channel = |n: f64| {
+        let k = (n + hue / 30.0) % 12.0;
+        let value = lightness - chroma / 2.0 * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+        (value * 255.0).round() as u8
+    };
+    format!("#{:02x}{:02x}{:02x}", channel(0.0), channel(8.0), channel(4.0))
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Elimination {
+    pub player: usize,
+    pub tick: u64,
+}
+
+// Route of a naval attack: the water cells it sails through, then the coastal
+// cell it lands on
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Voyage {
+    pub route: Vec<(usize, usize)>,
+    pub landing: (usize, usize),
+    pub progress: usize,
+}
+
+// A directed push: each tick only the `width` frontier pixels closest to `point`
+// are taken, so the attack grows toward it instead of along the whole border
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct AttackFocus {
+    pub point: (usize, usize),
+    pub width: usize,
+}
+
+impl AttackFocus {
+    // Keeps the `width` pixels nearest the point, ties broken by position
+    fn narrow(&self, pixels: &mut Vec<(usize, usize)>) {
+        let (px, py) = (self.point.0 as i64, self.point.1 as i64);
+        pixels.sort_unstable_by_key(|&(x, y)| ((x as i64 - px).pow(2) + (y as i64 - py).pow(2), x, y));
+        pixels.truncate(self.width);
     }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AttackMovement {
     pub source: usize,
     pub target: usize,
@@ -79,6 +172,11 @@ pub struct AttackMovement {
     pub border_pixels: Vec<(usize, usize)>,
     pub is_started: bool,
     cached_grid_size: Option<(usize, usize)>,
+    // Set while a naval attack is at sea, it expands like a land attack once landed
+    #[serde(default)]
+    pub voyage: Option<Voyage>,
+    #[serde(default)]
+    pub focus: Option<AttackFocus>,
 }
 
 impl AttackMovement {
@@ -90,15 +188,68 @@ impl AttackMovement {
             border_pixels: Vec::with_capacity(investment as usize),
             is_started: false,
             cached_grid_size: None,
+            voyage: None,
+            focus: None,
+        }
+    }
+
+    pub fn with_focus(mut self, focus: Option<AttackFocus>) -> Self {
+        self.focus = focus;
+        self
+    }
+
+    pub fn naval(source: usize, target: usize, investment: i32, route: Vec<(usize, usize)>, landing: (usize, usize)) -> Self {
+        AttackMovement {
+            voyage: Some(Voyage { route, landing, progress: 0 }),
+            ..Self::new(source, target, investment)
         }
     }
 
-    pub fn start(&mut self, grid: &Grid) {
+    pub fn is_at_sea(&self) -> bool {
+        self.voyage.is_some()
+    }
+
+    // Moves a naval attack up to `speed` cells along its route, paying `cost_per_cell`
+    // for each. Returns true once the attack is on land, from then on it expands
+    // from its landing cell.
+    pub fn sail(&mut self, speed: usize, cost_per_cell: i32) -> bool {
+        let Some(voyage) = &mut self.voyage else {
+            return true;
+        };
+        let moved = speed.min(voyage.route.len() - voyage.progress);
+        voyage.progress += moved;
+        self.investment -= moved as i32 * cost_per_cell;
+        if voyage.progress < voyage.route.len() {
+            return false;
+        }
+
+        self.border_pixels = vec![voyage.landing];
+        self.is_started = true;
+        self.voyage = None;
+        true
+    }
+
+    pub fn start(&mut self, grid: &Grid, terrain: &TerrainMap) {
         self.cache_grid_size(grid);
-        self.border_pixels = self.find_start_pixels(grid);
+        let mut pixels = self.find_start_pixels(grid, terrain);
+        // A pixel touching several source pixels is found once per contact
+        pixels.sort_unstable();
+        pixels.dedup();
+        if let Some(focus) = &self.focus {
+            focus.narrow(&mut pixels);
+        }
+        self.border_pixels = pixels;
         self.is_started = true;
     }
 
+    // The pixels a targeted attack captured this tick become the frontier it grows
+    // from next, untargeted and naval attacks keep the one they started from
+    pub fn advance(&mut self, captured: Vec<(usize, usize)>) {
+        if self.focus.is_some() {
+            self.border_pixels = captured;
+        }
+    }
+
     #[inline]
     fn cache_grid_size(&mut self, grid: &Grid) {
         if self.cached_grid_size.is_none() {
@@ -106,34 +257,34 @@ impl AttackMovement {
         }
     }
 
-    fn find_start_pixels(&self, grid: &Grid) -> Vec<(usize, usize)> {
+    fn find_start_pixels(&self, grid: &Grid, terrain: &TerrainMap) -> Vec<(usize, usize)> {
         let (grid_width, grid_height) = self.cached_grid_size.unwrap_or((grid[0].len(), grid.len()));
         let mut pixels = Vec::with_capacity(grid_width.min(grid_height));
         let mut source_pixels = Vec::with_capacity(grid_width);
         const DIRECTIONS: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
-        
-        for y in 0..grid_height {
+
+        for (y, row) in grid.iter().enumerate().take(grid_height) {
             source_pixels.clear();
-            let row = &grid[y];
-            
-            for x in 0..grid_width {
-                if let Some(id) = row[x] {
-                    if id == self.source {
-                        source_pixels.push(x);
-                    }
+
+            for (x, cell) in row.iter().enumerate().take(grid_width) {
+                if *cell == Some(self.source) {
+                    source_pixels.push(x);
                 }
             }
-            
+
             for &x in &source_pixels {
                 for &(dx, dy) in &DIRECTIONS {
                     let new_x = x as i32 + dx;
                     let new_y = y as i32 + dy;
-                    
+
                     if new_x >= 0 && new_x < grid_width as i32 &&
                        new_y >= 0 && new_y < grid_height as i32 {
                         let new_x = new_x as usize;
                         let new_y = new_y as usize;
-                        
+                        if !terrain.is_passable(new_x, new_y) {
+                            continue;
+                        }
+
                         match grid[new_y][new_x] {
                             Some(id) if id == self.target => pixels.push((new_x, new_y)),
                             None if self.target == usize::MAX => pixels.push((new_x, new_y)),
@@ -143,26 +294,30 @@ impl AttackMovement {
                 }
             }
         }
-        
+
         pixels
     }
 
-    pub fn get_next_pixels(&self, grid: &Grid) -> Vec<(usize, usize)> {
+    // Water is never captured, attacks flow around it
+    pub fn get_next_pixels(&self, grid: &Grid, terrain: &TerrainMap) -> Vec<(usize, usize)> {
         let (grid_width, grid_height) = self.cached_grid_size.unwrap_or((grid[0].len(), grid.len()));
         let mut next_pixels = HashSet::with_capacity(self.border_pixels.len() * 5); // Increased capacity for center position
         const DIRECTIONS: [(i32, i32); 5] = [(0, 0), (0, 1), (1, 0), (0, -1), (-1, 0)]; // Added (0, 0) for center position
-        
+
         for chunk in self.border_pixels.chunks(64) {
             for &(x, y) in chunk {
                 for &(dx, dy) in &DIRECTIONS {
                     let new_x = x as i32 + dx;
                     let new_y = y as i32 + dy;
-                    
+
                     if new_x >= 0 && new_x < grid_width as i32 &&
                        new_y >= 0 && new_y < grid_height as i32 {
                         let new_x = new_x as usize;
                         let new_y = new_y as usize;
-                        
+                        if !terrain.is_passable(new_x, new_y) {
+                            continue;
+                        }
+
                         match grid[new_y][new_x] {
                             Some(id) if id == self.target => next_pixels.insert((new_x, new_y)),
                             None if self.target == usize::MAX => next_pixels.insert((new_x, new_y)),
@@ -173,7 +328,12 @@ impl AttackMovement {
             }
         }
 
-        let result = next_pixels.into_iter().collect::<Vec<_>>();
+        // Sort so the result does not depend on hash iteration order
+        let mut result = next_pixels.into_iter().collect::<Vec<_>>();
+        result.sort_unstable();
+        if let Some(focus) = &self.focus {
+            focus.narrow(&mut result);
+        }
         result
     }
 }
@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde_json::json;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::modules::game::GameState;
+use super::replay::save_replay;
+
+pub const SNAPSHOT_DIR: &str = "snapshots";
+
+// Snapshot names end up in file paths, keep them to a safe character set
+fn snapshot_path(name: &str) -> Option<PathBuf> {
+    let valid = !name.is_empty() && name.len() <= 64 &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| PathBuf::from(SNAPSHOT_DIR).join(format!("{}.json", name)))
+}
+
+pub(super) fn reply(status: StatusCode, body: serde_json::Value) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&body), status).into_response()
+}
+
+async fn save_snapshot(name: String, game_state: Arc<RwLock<GameState>>) -> Result<warp::reply::Response, Rejection> {
+    let Some(path) = snapshot_path(&name) else {
+        return Ok(reply(StatusCode::BAD_REQUEST, json!({ "error": "invalid snapshot name" })));
+    };
+
+    // Serialize under the read lock, write the file once the game can tick again
+    let (contents, tick) = {
+        let state = game_state.read().await;
+        (state.to_snapshot_string(), state.tick)
+    };
+    let contents = match contents {
+        Ok(contents) => contents,
+        Err(e) => return Ok(reply(StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": e.to_string() }))),
+    };
+
+    let written = async {
+        tokio::fs::create_dir_all(SNAPSHOT_DIR).await?;
+        tokio::fs::write(&path, contents).await
+    };
+    match written.await {
+        Ok(()) => {
+            println!("Saved snapshot '{}' at tick {}", name, tick);
+            Ok(reply(StatusCode::OK, json!({ "name": name, "tick": tick })))
+        }
+        Err(e) => Ok(reply(StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": e.to_string() }))),
+    }
+}
+
+async fn load_snapshot(name: String, game_state: Arc<RwLock<GameState>>) -> Result<warp::reply::Response, Rejection> {
+    let Some(path) = snapshot_path(&name) else {
+        return Ok(reply(StatusCode::BAD_REQUEST, json!({ "error": "invalid snapshot name" })));
+    };
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) => return Ok(reply(StatusCode::NOT_FOUND, json!({ "error": e.to_string() }))),
+    };
+
+    match GameState::from_snapshot_str(&contents) {
+        Ok(restored) => {
+            let tick = restored.tick;
+            game_state.write().await.restore(restored);
+            println!("Restored snapshot '{}' at tick {}", name, tick);
+            Ok(reply(StatusCode::OK, json!({ "name": name, "tick": tick })))
+        }
+        Err(e) => Ok(reply(StatusCode::UNPROCESSABLE_ENTITY, json!({ "error": e.to_string() }))),
+    }
+}
+
+async fn save_current_replay(game_state: Arc<RwLock<GameState>>) -> Result<warp::reply::Response, Rejection> {
+    let Some(log) = game_state.read().await.replay_log() else {
+        return Ok(reply(StatusCode::CONFLICT, json!({ "error": "the current game is not being recorded" })));
+    };
+    let ticks = log.total_ticks();
+    match save_replay(log).await {
+        Ok(id) => {
+            println!("Saved replay '{}' ({} ticks)", id, ticks);
+            Ok(reply(StatusCode::OK, json!({ "id": id, "ticks": ticks })))
+        }
+        Err(e) => Ok(reply(StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": e }))),
+    }
+}
+
+async fn list_snapshots() -> Result<warp::reply::Response, Rejection> {
+    let mut names = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(SNAPSHOT_DIR).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    Ok(reply(StatusCode::OK, json!({ "snapshots": names })))
+}
+
+pub fn routes(game_state: Arc<RwLock<GameState>>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let with_state = warp::any().map(move || game_state.clone());
+
+    let list = warp::path!("admin" / "snapshots")
+        .and(warp::get())
+        .and_then(list_snapshots);
+
+    let save = warp::path!("admin" / "snapshots" / String)
+        .and(warp::post())
+        .and(with_state.clone())
+        .and_then(save_snapshot);
+
+    let load = warp::path!("admin" / "snapshots" / String / "load")
+        .and(warp::post())
+        .and(with_state.clone())
+        .and_then(load_snapshot);
+
+    let replay = warp::path!("admin" / "replays")
+        .and(warp::post())
+        .and(with_state)
+        .and_then(save_current_replay);
+
+    list.or(save).or(load).or(replay)
+}
@@ -0,0 +1,383 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{self, Duration};
+use warp::ws::{Message, WebSocket};
+use warp::{Filter, Rejection, Reply};
+
+use super::rooms::{CreateRoom, RoomManager, Seat};
+
+// How often the queue is checked for a match, waiting players hear their
+// position each time
+const MATCH_INTERVAL: Duration = Duration::from_millis(500);
+pub const MAX_NAME_LENGTH: usize = 24;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LobbyRequest {
+    // `color` is a CSS hex colour like "#ff8800"
+    Join { name: String, color: String },
+    Leave,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LobbyMessage<'a> {
+    // Sent on joining and while waiting. The match starts once `needed` players
+    // wait, or `timeout_ms` after the first of them joined.
+    Queued {
+        position: usize,
+        waiting: usize,
+        needed: usize,
+        timeout_ms: u64,
+    },
+    Left,
+    // Connect to `/ws/{room}?seat={seat}` to play nation `player`
+    Matched {
+        room: &'a str,
+        player: usize,
+        seat: &'a str,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl LobbyMessage<'_> {
+    fn to_text(&self) -> Message {
+        Message::text(serde_json::to_string(self).expect("lobby message serializes"))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LobbyError {
+    InvalidName,
+    InvalidColor(String),
+    AlreadyQueued,
+    NotQueued,
+}
+
+impl fmt::Display for LobbyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LobbyError::InvalidName => write!(f, "names are 1 to {} characters long", MAX_NAME_LENGTH),
+            LobbyError::InvalidColor(color) => write!(f, "invalid colour '{}', expected #rrggbb", color),
+            LobbyError::AlreadyQueued => write!(f, "already waiting for a game"),
+            LobbyError::NotQueued => write!(f, "not waiting for a game"),
+        }
+    }
+}
+
+impl std::error::Error for LobbyError {}
+
+fn validate_name(name: &str) -> Result<String, LobbyError> {
+    let name = name.trim();
+    let length = name.chars().count();
+    if length == 0 || length > MAX_NAME_LENGTH || name.chars().any(char::is_control) {
+        return Err(LobbyError::InvalidName);
+    }
+    Ok(name.to_string())
+}
+
+fn validate_color(color: &str) -> Result<String, LobbyError> {
+    let valid = color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err(LobbyError::InvalidColor(color.to_string()));
+    }
+    Ok(color.to_ascii_lowercase())
+}
+
+struct Ticket {
+    id: u64,
+    name: String,
+    color: String,
+    joined: Instant,
+    tx: mpsc::Sender<Message>,
+}
+
+// Queues players from the lobby socket and seats them together in a new room
+pub struct Matchmaker {
+    rooms: Arc<RoomManager>,
+    queue: Mutex<VecDeque<Ticket>>,
+    next_ticket: AtomicU64,
+}
+
+impl Matchmaker {
+    pub fn new(rooms: Arc<RoomManager>) -> Self {
+        Matchmaker { rooms, queue: Mutex::new(VecDeque::new()), next_ticket: AtomicU64::new(1) }
+    }
+
+    // Players a match waits for, never more than a game has nations
+    fn needed(&self) -> usize {
+        let config = self.rooms.config();
+        config.match_players.min(config.num_players)
+    }
+
+    async fn join(&self, name: &str, color: &str, tx: mpsc::Sender<Message>) -> Result<u64, LobbyError> {
+        let (name, color) = (validate_name(name)?, validate_color(color)?);
+        let id = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        self.queue.lock().await.push_back(Ticket { id, name, color, joined: Instant::now(), tx });
+        Ok(id)
+    }
+
+    async fn leave(&self, ticket: u64) -> Result<(), LobbyError> {
+        let mut queue = self.queue.lock().await;
+        let position = queue.iter().position(|t| t.id == ticket).ok_or(LobbyError::NotQueued)?;
+        queue.remove(position);
+        Ok(())
+    }
+
+    async fn is_queued(&self, ticket: u64) -> bool {
+        self.queue.lock().await.iter().any(|t| t.id == ticket)
+    }
+
+    // Opens a room for the players at the front of the queue once enough of
+    // them wait or the first has waited long enough, tells the others where
+    // they stand
+    pub async fn match_players(&self) {
+        let needed = self.needed();
+        let timeout = Duration::from_millis(self.rooms.config().match_timeout_ms);
+        // Take the tickets out under the lock and release it before awaiting the room, joins and
+        // leaves should not wait on map generation
+        let tickets: Vec<Ticket> = {
+            let mut queue = self.queue.lock().await;
+            queue.retain(|ticket| !ticket.tx.is_closed());
+            let Some(first) = queue.front() else {
+                return;
+            };
+            if queue.len() >= needed || first.joined.elapsed() >= timeout {
+                let count = needed.min(queue.len());
+                queue.drain(..count).collect()
+            } else {
+                Vec::new()
+            }
+        };
+
+        if !tickets.is_empty() {
+            match self.rooms.create(CreateRoom::default()).await {
+                Ok(room) => {
+                    let players: Vec<usize> = room.state.read().await.players.iter().map(|p| p.id).collect();
+                    println!("Lobby matched {} player(s) into room '{}'", tickets.len(), room.id);
+                    for (ticket, player) in tickets.into_iter().zip(players) {
+                        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+                        let message = LobbyMessage::Matched { room: &room.id, player, seat: &token }.to_text();
+                        room.reserve(Seat { player, name: ticket.name, color: ticket.color, token });
+                        let _ = ticket.tx.send(message).await;
+                    }
+                }
+                Err(e) => {
+                    // Back to the front of the queue, the next round tries again
+                    println!("Lobby could not open a room: {}", e);
+                    let mut queue = self.queue.lock().await;
+                    for ticket in tickets.into_iter().rev() {
+                        let _ = ticket.tx.try_send(LobbyMessage::Error { message: e.to_string() }.to_text());
+                        queue.push_front(ticket);
+                    }
+                }
+            }
+        }
+
+        let queue = self.queue.lock().await;
+        let waiting = queue.len();
+        for (position, ticket) in queue.iter().enumerate() {
+            let message = LobbyMessage::Queued {
+                position: position + 1,
+                waiting,
+                needed,
+                timeout_ms: timeout.saturating_sub(ticket.joined.elapsed()).as_millis() as u64,
+            };
+            let _ = ticket.tx.try_send(message.to_text());
+        }
+    }
+}
+
+pub async fn run_matchmaker(matchmaker: Arc<Matchmaker>) {
+    let mut interval = time::interval(MATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        matchmaker.match_players().await;
+    }
+}
+
+async fn handle_lobby_websocket(ws: WebSocket, matchmaker: Arc<Matchmaker>) {
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let (tx, mut rx) = mpsc::channel::<Message>(64);
+    let forward = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if ws_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = ws_tx.send(Message::close()).await;
+    });
+
+    let mut ticket = None;
+    while let Some(Ok(message)) = ws_rx.next().await {
+        if message.is_close() {
+            break;
+        }
+        let Ok(text) = message.to_str() else {
+            continue;
+        };
+        // A matched ticket has left the queue, the player may queue again
+        if let Some(id) = ticket {
+            if !matchmaker.is_queued(id).await {
+                ticket = None;
+            }
+        }
+        let reply = match serde_json::from_str(text) {
+            Ok(LobbyRequest::Join { .. }) if ticket.is_some() => Err(LobbyError::AlreadyQueued),
+            Ok(LobbyRequest::Join { name, color }) => matchmaker.join(&name, &color, tx.clone()).await.map(|id| {
+                ticket = Some(id);
+                None
+            }),
+            Ok(LobbyRequest::Leave) => match ticket.take() {
+                Some(id) => matchmaker.leave(id).await.map(|()| Some(LobbyMessage::Left)),
+                None => Err(LobbyError::NotQueued),
+            },
+            Err(e) => Ok(Some(LobbyMessage::Error { message: format!("invalid message: {}", e) })),
+        };
+        let message = match reply {
+            Ok(Some(message)) => message,
+            // Joining is answered by the queue position on the next round
+            Ok(None) => continue,
+            Err(e) => LobbyMessage::Error { message: e.to_string() },
+        };
+        if tx.send(message.to_text()).await.is_err() {
+            break;
+        }
+    }
+
+    if let Some(id) = ticket {
+        let _ = matchmaker.leave(id).await;
+    }
+    drop(tx);
+    let _ = forward.await;
+}
+
+pub fn routes(matchmaker: Arc<Matchmaker>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("lobby" / "ws")
+        .and(warp::ws())
+        .map(move |ws: warp::ws::Ws| {
+            let matchmaker = matchmaker.clone();
+            ws.on_upgrade(move |socket| handle_lobby_websocket(socket, matchmaker))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::GameConfig;
+    use crate::modules::game::testing::test_config;
+
+    fn matchmaker(match_players: usize, match_timeout_ms: u64) -> Matchmaker {
+        let config = GameConfig { match_players, match_timeout_ms, ..test_config(60, 40, 4, 3) };
+        Matchmaker::new(Arc::new(RoomManager::new(config)))
+    }
+
+    async fn next_message(rx: &mut mpsc::Receiver<Message>) -> serde_json::Value {
+        let message = rx.recv().await.expect("a lobby message");
+        serde_json::from_str(message.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn names_and_colours_are_checked() {
+        assert_eq!(validate_name("  Ada "), Ok("Ada".to_string()));
+        assert_eq!(validate_name(""), Err(LobbyError::InvalidName));
+        assert_eq!(validate_name(&"x".repeat(MAX_NAME_LENGTH + 1)), Err(LobbyError::InvalidName));
+        assert_eq!(validate_color("#FF8800"), Ok("#ff8800".to_string()));
+        assert!(validate_color("red").is_err() && validate_color("#12345g").is_err());
+    }
+
+    #[tokio::test]
+    async fn full_queues_are_seated_in_a_room() {
+        let matchmaker = matchmaker(2, 60_000);
+        let (ada_tx, mut ada) = mpsc::channel(8);
+        let (bob_tx, mut bob) = mpsc::channel(8);
+        matchmaker.join("Ada", "#ff0000", ada_tx).await.unwrap();
+        matchmaker.match_players().await;
+        let queued = next_message(&mut ada).await;
+        assert_eq!((queued["type"].as_str(), queued["position"].as_u64(), queued["needed"].as_u64()), (Some("queued"), Some(1), Some(2)));
+
+        matchmaker.join("Bob", "#0000ff", bob_tx).await.unwrap();
+        matchmaker.match_players().await;
+        let (ada_match, bob_match) = (next_message(&mut ada).await, next_message(&mut bob).await);
+        assert_eq!(ada_match["type"], "matched");
+        assert_eq!(ada_match["room"], bob_match["room"]);
+        assert_ne!(ada_match["player"], bob_match["player"]);
+
+        let room = matchmaker.rooms.get(ada_match["room"].as_str().unwrap()).await.unwrap();
+        let seat = room.seat(bob_match["seat"].as_str().unwrap()).expect("bob has a seat");
+        assert_eq!(Some(seat.player as u64), bob_match["player"].as_u64());
+        assert_eq!(seat.name, "Bob");
+        assert!(room.seat("guess").is_none());
+        assert!(matchmaker.queue.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn lone_players_get_bots_after_the_timeout() {
+        let matchmaker = matchmaker(4, 0);
+        let (tx, mut rx) = mpsc::channel(8);
+        matchmaker.join("Ada", "#00ff00", tx).await.unwrap();
+        matchmaker.match_players().await;
+        let matched = next_message(&mut rx).await;
+        assert_eq!(matched["type"], "matched");
+        let room = matchmaker.rooms.get(matched["room"].as_str().unwrap()).await.unwrap();
+        // The other three nations are left to the bots
+        assert_eq!(room.state.read().await.players.len(), 4);
+        assert_eq!(room.info().await.seats.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tickets_return_to_the_front_when_no_room_opens() {
+        let config = GameConfig { match_players: 2, map: Some("no-such-map".into()), ..test_config(60, 40, 4, 3) };
+        let matchmaker = Matchmaker::new(Arc::new(RoomManager::new(config)));
+        let (ada_tx, mut ada) = mpsc::channel(8);
+        let (bob_tx, mut bob) = mpsc::channel(8);
+        matchmaker.join("Ada", "#ff0000", ada_tx).await.unwrap();
+        matchmaker.join("Bob", "#0000ff", bob_tx).await.unwrap();
+
+        matchmaker.match_players().await;
+        assert_eq!(next_message(&mut ada).await["type"], "error");
+        assert_eq!(next_message(&mut bob).await["type"], "error");
+        let (ada_queued, bob_queued) = (next_message(&mut ada).await, next_message(&mut bob).await);
+        assert_eq!((ada_queued["type"].as_str(), ada_queued["position"].as_u64()), (Some("queued"), Some(1)));
+        assert_eq!(bob_queued["position"].as_u64(), Some(2));
+        assert!(matchmaker.rooms.list().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaving_and_disconnected_players_drop_out_of_the_queue() {
+        let matchmaker = matchmaker(3, 60_000);
+        let (ada_tx, mut ada) = mpsc::channel(8);
+        let (bob_tx, mut bob) = mpsc::channel(8);
+        let (carol_tx, carol) = mpsc::channel(8);
+        matchmaker.join("Ada", "#ff0000", ada_tx).await.unwrap();
+        let first = matchmaker.join("Bob", "#0000ff", bob_tx.clone()).await.unwrap();
+        matchmaker.join("Carol", "#00ff00", carol_tx).await.unwrap();
+
+        // Bob leaves and queues again at the back, Carol closes her socket
+        matchmaker.leave(first).await.unwrap();
+        assert_eq!(matchmaker.leave(first).await, Err(LobbyError::NotQueued));
+        assert!(!matchmaker.is_queued(first).await);
+        let second = matchmaker.join("Bob", "#0000ff", bob_tx).await.unwrap();
+        assert_ne!(first, second);
+        drop(carol);
+
+        // Three tickets were taken but only two players still wait, nobody is matched
+        matchmaker.match_players().await;
+        let (ada_queued, bob_queued) = (next_message(&mut ada).await, next_message(&mut bob).await);
+        assert_eq!(ada_queued["type"], "queued");
+        assert_eq!((ada_queued["position"].as_u64(), ada_queued["waiting"].as_u64()), (Some(1), Some(2)));
+        assert_eq!((bob_queued["position"].as_u64(), bob_queued["waiting"].as_u64()), (Some(2), Some(2)));
+        let names: Vec<String> = matchmaker.queue.lock().await.iter().map(|t| t.name.clone()).collect();
+        assert_eq!(names, vec!["Ada", "Bob"]);
+        assert!(matchmaker.rooms.list().await.is_empty());
+    }
+}
@@ -1,3 +1,8 @@
 mod websocket;
+pub mod admin;
+pub mod replay;
+pub mod protocol;
+pub mod rooms;
+pub mod lobby;
 
 pub use websocket::handle_websocket;
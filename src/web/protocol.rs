@@ -0,0 +1,503 @@
+use serde::{Deserialize, Serialize};
+use warp::ws::Message;
+
+use crate::modules::game::{
+    Diplomacy, DiplomacyEvent, GameResult, GameState, LeaderboardEntry, LifecycleEvent, Phase, PlayerStats,
+    StatsHistory, StatsTracker, Treaty, STATS_HISTORY,
+};
+use crate::modules::types::{Grid, PlayerKind};
+
+// Ticks between two broadcast keyframes, late joiners and clients that missed
+// a delta without asking for a resync recover at the next one
+pub const KEYFRAME_INTERVAL: u64 = 50;
+// Ticks between two leaderboards while the game runs
+pub const LEADERBOARD_INTERVAL: u64 = 10;
+
+// How a nation is shown to clients
+#[derive(Serialize)]
+pub struct RosterEntry<'a> {
+    pub id: usize,
+    pub name: &'a str,
+    pub color: &'a str,
+    pub kind: PlayerKind,
+    pub team: Option<usize>,
+}
+
+// Grid updates are tagged with `seq`, the tick they describe. A client that sees
+// a delta whose seq is not the previous one + 1 missed something and asks for a resync.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage<'a> {
+    // Sent before the first keyframe and on resync, one string of terrain symbols
+    // per row ('.' plains, '~' water, '^' mountain)
+    Terrain {
+        width: usize,
+        height: usize,
+        rows: Vec<String>,
+    },
+    // Team of every player id, null for nations playing alone. Sent with the terrain.
+    Teams {
+        teams: Vec<Option<usize>>,
+    },
+    // Name, colour and kind of every nation still in the game. Sent on connect
+    // and whenever one of them changes, clients keep the entries of nations
+    // that have been eliminated since.
+    Roster {
+        players: Vec<RosterEntry<'a>>,
+    },
+    // Nations standing by rank, every LEADERBOARD_INTERVAL ticks and on connect
+    Leaderboard {
+        tick: u64,
+        entries: Vec<LeaderboardEntry>,
+    },
+    // Area, resources, income, attacks, cells won and lost and kills of every
+    // nation, broadcast after each tick of a running game
+    Stats {
+        tick: u64,
+        players: &'a [PlayerStats],
+    },
+    // The last STATS_HISTORY ticks of stats, sent on connect for sparklines.
+    // Clients extend it with the stats that follow.
+    StatsHistory {
+        tick: u64,
+        players: Vec<StatsHistory>,
+    },
+    Keyframe {
+        seq: u64,
+        grid: &'a Grid,
+    },
+    Delta {
+        seq: u64,
+        // [x, y, owner] triples, owner is null for empty land
+        cells: Vec<(usize, usize, Option<usize>)>,
+    },
+    // Replies to player commands, only sent to the connection that issued them
+    Claimed {
+        player: usize,
+    },
+    Released {
+        player: usize,
+    },
+    AttackStarted {
+        target: Option<usize>,
+        percent: f64,
+    },
+    NavalAttackStarted {
+        x: usize,
+        y: usize,
+        percent: f64,
+    },
+    Error {
+        message: String,
+    },
+    // Current alliances, pacts and pending proposals, sent on connect, on resync
+    // and in reply to diplomacy commands
+    Treaties(&'a Diplomacy),
+    // Broadcast to everyone as treaties are proposed, signed, broken or run out
+    Diplomacy {
+        event: DiplomacyEvent,
+    },
+    // Sent on connect and whenever the game moves to another phase. Lobby and
+    // countdown clients count `remaining` down themselves, one per `tick_ms`.
+    Phase {
+        #[serde(flatten)]
+        phase: Phase,
+        tick_ms: u64,
+    },
+    // Broadcast when the game is won, and sent on connect while it is over
+    GameOver {
+        result: &'a GameResult,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Resync,
+    Claim { player: usize },
+    Release,
+    // Empty land is attacked when `target` is null or missing, `toward` is an
+    // optional [x, y] cell the attack pushes for
+    Attack {
+        #[serde(default)]
+        target: Option<usize>,
+        percent: f64,
+        #[serde(default)]
+        toward: Option<(usize, usize)>,
+    },
+    // Sends a boat to land on the coastal cell (x, y)
+    NavalAttack {
+        x: usize,
+        y: usize,
+        percent: f64,
+    },
+    ProposeTreaty { to: usize, treaty: Treaty },
+    AcceptTreaty { from: usize },
+    BreakTreaty { with: usize },
+}
+
+impl ServerMessage<'_> {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("server message serializes")
+    }
+
+    pub fn to_text(&self) -> Message {
+        Message::text(self.to_json())
+    }
+}
+
+// Chosen per connection with `/ws?format=binary`, JSON stays the default for debugging
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Binary,
+}
+
+#[derive(Deserialize)]
+pub struct SocketOptions {
+    #[serde(default)]
+    pub format: WireFormat,
+    // Token of a seat the lobby reserved, `/ws/{room}?seat=...` claims its nation
+    #[serde(default)]
+    pub seat: Option<String>,
+}
+
+// Binary frames, all integers little-endian:
+//
+//   offset  size  field
+//   0       1     kind: 0 = keyframe, 1 = delta
+//   1       1     owner width in bytes: 1 when player count < 255, 2 otherwise
+//   2       2     grid width
+//   4       2     grid height
+//   6       2     player count
+//   8       8     seq (tick)
+//
+// Owners are encoded as 0 for empty land and id + 1 for a nation, using the owner width.
+//
+// Keyframe body: for every row from top to bottom, runs of (u16 length, owner) whose
+// lengths add up to the grid width. Rows always start a new run.
+//
+// Delta body: u32 cell count, then (u16 x, u16 y, owner) for each changed cell.
+pub const BINARY_HEADER_SIZE: usize = 16;
+pub const BINARY_KEYFRAME: u8 = 0;
+pub const BINARY_DELTA: u8 = 1;
+
+// One grid update encoded once for every wire format and shared by all subscribers.
+// `events` are JSON messages for things that happened during the tick, they are
+// sent ahead of the grid in both formats. Outside of running games there is no
+// grid update, only events.
+pub struct EncodedUpdate {
+    pub grid: Option<(String, Vec<u8>)>,
+    pub events: Vec<String>,
+}
+
+impl EncodedUpdate {
+    pub fn to_message(&self, format: WireFormat) -> Option<Message> {
+        let (json, binary) = self.grid.as_ref()?;
+        Some(match format {
+            WireFormat::Json => Message::text(json.clone()),
+            WireFormat::Binary => Message::binary(binary.clone()),
+        })
+    }
+}
+
+pub fn phase(state: &GameState) -> Message {
+    ServerMessage::Phase { phase: state.phase, tick_ms: state.config.update_interval_ms }.to_text()
+}
+
+// The result of a finished game, for clients joining after it ended
+pub fn game_over(state: &GameState) -> Option<Message> {
+    state.result.as_ref().map(|result| ServerMessage::GameOver { result }.to_text())
+}
+
+pub fn treaties(state: &GameState) -> Message {
+    Message::text(treaties_message(state))
+}
+
+fn treaties_message(state: &GameState) -> String {
+    ServerMessage::Treaties(&state.diplomacy).to_json()
+}
+
+pub fn teams(state: &GameState) -> Message {
+    Message::text(teams_message(state))
+}
+
+fn teams_message(state: &GameState) -> String {
+    ServerMessage::Teams {
+        teams: (0..state.config.num_players).map(|id| state.config.team_for(id)).collect(),
+    }.to_json()
+}
+
+pub fn roster(state: &GameState) -> Message {
+    Message::text(roster_message(state))
+}
+
+pub fn roster_message(state: &GameState) -> String {
+    let players = state.players.iter()
+        .map(|p| RosterEntry { id: p.id, name: &p.name, color: &p.color, kind: p.kind, team: p.team })
+        .collect();
+    ServerMessage::Roster { players }.to_json()
+}
+
+pub fn leaderboard(state: &GameState) -> Message {
+    Message::text(leaderboard_message(state))
+}
+
+fn leaderboard_message(state: &GameState) -> String {
+    ServerMessage::Leaderboard { tick: state.tick, entries: state.leaderboard() }.to_json()
+}
+
+pub fn stats_message(stats: &StatsTracker) -> Option<String> {
+    let tick = stats.tick()?;
+    Some(ServerMessage::Stats { tick, players: stats.current() }.to_json())
+}
+
+pub fn stats_history(stats: &StatsTracker) -> Option<Message> {
+    let tick = stats.tick()?;
+    Some(ServerMessage::StatsHistory { tick, players: stats.history(STATS_HISTORY) }.to_text())
+}
+
+// Terrain never changes during a game, it is always sent as JSON
+pub fn terrain(state: &GameState) -> Message {
+    Message::text(terrain_message(state))
+}
+
+fn terrain_message(state: &GameState) -> String {
+    ServerMessage::Terrain {
+        width: state.terrain.width(),
+        height: state.terrain.height(),
+        rows: state.terrain.rows(),
+    }.to_json()
+}
+
+pub fn keyframe_message(state: &GameState) -> String {
+    let message = ServerMessage::Keyframe { seq: state.tick, grid: &state.grid };
+    serde_json::to_string(&message).expect("keyframe serializes")
+}
+
+pub fn delta_message(state: &GameState) -> String {
+    let cells = state.changed_cells()
+        .iter()
+        .map(|&(x, y, _)| (x, y, state.grid[y][x]))
+        .collect();
+    let message = ServerMessage::Delta { seq: state.tick, cells };
+    serde_json::to_string(&message).expect("delta serializes")
+}
+
+fn owner_width(state: &GameState) -> u8 {
+    if state.config.num_players < u8::MAX as usize { 1 } else { 2 }
+}
+
+fn write_header(buf: &mut Vec<u8>, kind: u8, state: &GameState) {
+    buf.push(kind);
+    buf.push(owner_width(state));
+    buf.extend_from_slice(&(state.config.grid_width as u16).to_le_bytes());
+    buf.extend_from_slice(&(state.config.grid_height as u16).to_le_bytes());
+    buf.extend_from_slice(&(state.config.num_players as u16).to_le_bytes());
+    buf.extend_from_slice(&state.tick.to_le_bytes());
+}
+
+#[inline]
+fn write_owner(buf: &mut Vec<u8>, owner: Option<usize>, width: u8) {
+    let code = owner.map_or(0, |id| id as u16 + 1);
+    if width == 1 {
+        buf.push(code as u8);
+    } else {
+        buf.extend_from_slice(&code.to_le_bytes());
+    }
+}
+
+pub fn binary_keyframe(state: &GameState) -> Vec<u8> {
+    let width = owner_width(state);
+    let mut buf = Vec::with_capacity(BINARY_HEADER_SIZE + state.grid.len() * 16);
+    write_header(&mut buf, BINARY_KEYFRAME, state);
+
+    for row in &state.grid {
+        let mut cells = row.iter();
+        let Some(&first) = cells.next() else {
+            continue;
+        };
+        let (mut owner, mut length) = (first, 1u16);
+        for &cell in cells {
+            if cell == owner && length < u16::MAX {
+                length += 1;
+            } else {
+                buf.extend_from_slice(&length.to_le_bytes());
+                write_owner(&mut buf, owner, width);
+                (owner, length) = (cell, 1);
+            }
+        }
+        buf.extend_from_slice(&length.to_le_bytes());
+        write_owner(&mut buf, owner, width);
+    }
+    buf
+}
+
+pub fn binary_delta(state: &GameState) -> Vec<u8> {
+    let width = owner_width(state);
+    let cells = state.changed_cells();
+    let mut buf = Vec::with_capacity(BINARY_HEADER_SIZE + 4 + cells.len() * (4 + width as usize));
+    write_header(&mut buf, BINARY_DELTA, state);
+
+    buf.extend_from_slice(&(cells.len() as u32).to_le_bytes());
+    for &(x, y, _) in cells {
+        buf.extend_from_slice(&(x as u16).to_le_bytes());
+        buf.extend_from_slice(&(y as u16).to_le_bytes());
+        write_owner(&mut buf, state.grid[y][x], width);
+    }
+    buf
+}
+
+pub fn keyframe(state: &GameState, format: WireFormat) -> Message {
+    match format {
+        WireFormat::Json => Message::text(keyframe_message(state)),
+        WireFormat::Binary => Message::binary(binary_keyframe(state)),
+    }
+}
+
+pub fn delta(state: &GameState, format: WireFormat) -> Message {
+    match format {
+        WireFormat::Json => Message::text(delta_message(state)),
+        WireFormat::Binary => Message::binary(binary_delta(state)),
+    }
+}
+
+// Messages for the events of the last server tick. A new game brings its
+// terrain, teams and treaties along, its first keyframe follows with the update.
+pub fn event_messages(state: &GameState, diplomacy: &[DiplomacyEvent], lifecycle: &[LifecycleEvent]) -> Vec<String> {
+    let mut messages = Vec::new();
+    for event in lifecycle {
+        match event {
+            LifecycleEvent::NewGame => {
+                messages.extend([terrain_message(state), teams_message(state), treaties_message(state)]);
+            }
+            LifecycleEvent::PhaseChanged(phase) => {
+                messages.push(ServerMessage::Phase { phase: *phase, tick_ms: state.config.update_interval_ms }.to_json());
+            }
+            LifecycleEvent::GameOver(result) => messages.push(ServerMessage::GameOver { result }.to_json()),
+        }
+    }
+    messages.extend(diplomacy.iter().map(|&event| ServerMessage::Diplomacy { event }.to_json()));
+    messages
+}
+
+// Encodes the state after a server tick given the last tick broadcast: nothing
+// when the game did not move, a keyframe when ticks were skipped or one is due,
+// a delta otherwise. The leaderboard goes along when due.
+pub fn encode_update(state: &GameState, last_broadcast_tick: Option<u64>, mut events: Vec<String>) -> EncodedUpdate {
+    if last_broadcast_tick == Some(state.tick) {
+        return EncodedUpdate { grid: None, events };
+    }
+    if state.tick.is_multiple_of(LEADERBOARD_INTERVAL) {
+        events.push(leaderboard_message(state));
+    }
+    let contiguous = last_broadcast_tick.is_some_and(|tick| tick + 1 == state.tick);
+    let grid = if !contiguous || state.tick.is_multiple_of(KEYFRAME_INTERVAL) {
+        (keyframe_message(state), binary_keyframe(state))
+    } else {
+        (delta_message(state), binary_delta(state))
+    };
+    EncodedUpdate { grid: Some(grid), events }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::game::testing::test_game;
+
+    fn decode_keyframe(frame: &[u8]) -> (u64, Grid) {
+        let u16_at = |offset: usize| u16::from_le_bytes([frame[offset], frame[offset + 1]]) as usize;
+        assert_eq!(frame[0], BINARY_KEYFRAME);
+        let owner_width = frame[1] as usize;
+        let (width, height) = (u16_at(2), u16_at(4));
+        let seq = u64::from_le_bytes(frame[8..16].try_into().unwrap());
+
+        let mut offset = BINARY_HEADER_SIZE;
+        let mut grid = Vec::with_capacity(height);
+        for _ in 0..height {
+            let mut row = Vec::with_capacity(width);
+            while row.len() < width {
+                let length = u16_at(offset);
+                let code = if owner_width == 1 { frame[offset + 2] as usize } else { u16_at(offset + 2) };
+                offset += 2 + owner_width;
+                row.extend(std::iter::repeat_n(code.checked_sub(1), length));
+            }
+            grid.push(row);
+        }
+        assert_eq!(offset, frame.len());
+        (seq, grid)
+    }
+
+    // Applies a binary delta to `grid` and returns its seq
+    fn apply_delta(frame: &[u8], grid: &mut Grid) -> u64 {
+        let u16_at = |offset: usize| u16::from_le_bytes([frame[offset], frame[offset + 1]]) as usize;
+        assert_eq!(frame[0], BINARY_DELTA);
+        let owner_width = frame[1] as usize;
+        let seq = u64::from_le_bytes(frame[8..16].try_into().unwrap());
+        let count = u32::from_le_bytes(frame[16..20].try_into().unwrap()) as usize;
+
+        let mut offset = BINARY_HEADER_SIZE + 4;
+        for _ in 0..count {
+            let (x, y) = (u16_at(offset), u16_at(offset + 2));
+            let code = if owner_width == 1 { frame[offset + 4] as usize } else { u16_at(offset + 4) };
+            grid[y][x] = code.checked_sub(1);
+            offset += 4 + owner_width;
+        }
+        assert_eq!(offset, frame.len());
+        seq
+    }
+
+    #[test]
+    fn binary_keyframe_round_trips() {
+        let mut state = test_game(120, 90, 6, 5);
+        for _ in 0..60 {
+            state.update();
+        }
+
+        let (seq, grid) = decode_keyframe(&binary_keyframe(&state));
+        assert_eq!(seq, state.tick);
+        assert_eq!(grid, state.grid);
+    }
+
+    #[test]
+    fn binary_deltas_round_trip() {
+        let mut state = test_game(120, 90, 6, 5);
+        let mut mirror = state.grid.clone();
+        for _ in 0..60 {
+            state.update();
+            assert_eq!(apply_delta(&binary_delta(&state), &mut mirror), state.tick);
+            assert_eq!(mirror, state.grid, "delta missed a change at tick {}", state.tick);
+        }
+
+        // Long running rooms go past what 32 bits hold without the seq wrapping
+        state.tick = u32::MAX as u64 + 3;
+        assert_eq!(apply_delta(&binary_delta(&state), &mut mirror), u32::MAX as u64 + 3);
+        assert_eq!(decode_keyframe(&binary_keyframe(&state)).0, u32::MAX as u64 + 3);
+    }
+
+    #[test]
+    fn skipped_ticks_are_resynced_with_a_keyframe() {
+        let mut state = test_game(120, 90, 6, 5);
+        for _ in 0..12 {
+            state.update();
+        }
+        let kind = |update: &EncodedUpdate| {
+            let (json, binary) = update.grid.as_ref().expect("a grid update");
+            let message: serde_json::Value = serde_json::from_str(json).unwrap();
+            assert_eq!(message["seq"], state.tick);
+            assert_eq!(binary[0], if message["type"] == "keyframe" { BINARY_KEYFRAME } else { BINARY_DELTA });
+            message["type"].as_str().unwrap().to_string()
+        };
+
+        assert_eq!(kind(&encode_update(&state, Some(state.tick - 1), Vec::new())), "delta");
+        // A gap or a subscriber that saw nothing yet gets the whole grid
+        assert_eq!(kind(&encode_update(&state, Some(state.tick - 2), Vec::new())), "keyframe");
+        assert_eq!(kind(&encode_update(&state, None, Vec::new())), "keyframe");
+        assert!(encode_update(&state, Some(state.tick), Vec::new()).grid.is_none());
+
+        // Clients that notice the gap themselves ask for one
+        assert!(matches!(serde_json::from_str(r#"{"type":"resync"}"#), Ok(ClientMessage::Resync)));
+    }
+}
@@ -0,0 +1,232 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::{sleep, Duration};
+use warp::ws::{Message, WebSocket};
+use warp::{Filter, Rejection, Reply};
+
+use crate::modules::game::{ReplayLog, ReplayPlayer};
+use super::protocol::{delta, keyframe, roster, terrain, SocketOptions, WireFormat};
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+
+pub const REPLAY_DIR: &str = "replays";
+
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 16.0;
+// Status heartbeat while paused, keeps the client's connection health check happy
+const PAUSED_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ReplayControl {
+    Play,
+    Pause,
+    Seek { tick: u64 },
+    Speed { speed: f64 },
+    Resync,
+}
+
+#[derive(Clone, Copy)]
+enum Frame {
+    Keyframe,
+    Delta,
+}
+
+#[derive(Serialize)]
+struct ReplayStatus {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    tick: u64,
+    total_ticks: u64,
+    paused: bool,
+    speed: f64,
+}
+
+// Replay ids end up in file paths, keep them to a safe character set
+pub fn replay_path(id: &str) -> Option<PathBuf> {
+    let valid = !id.is_empty() && id.len() <= 64 &&
+        id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| PathBuf::from(REPLAY_DIR).join(format!("{}.json", id)))
+}
+
+// Writes the log under a fresh id made of the game seed and the current time
+pub async fn save_replay(log: ReplayLog) -> Result<String, String> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let id = format!("{}-{}", log.seed, timestamp);
+    let path = replay_path(&id).ok_or_else(|| format!("invalid replay id {}", id))?;
+
+    tokio::task::spawn_blocking(move || log.save(&path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+async fn load_replay(id: &str) -> Option<ReplayLog> {
+    let path = replay_path(id)?;
+    let contents = tokio::fs::read_to_string(&path).await.ok()?;
+    match ReplayLog::from_json(&contents) {
+        Ok(log) => Some(log),
+        Err(e) => {
+            println!("Could not open replay {}: {}", id, e);
+            None
+        }
+    }
+}
+
+async fn list_replays() -> Result<impl Reply, Rejection> {
+    let mut ids = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(REPLAY_DIR).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+    }
+    ids.sort();
+    Ok(warp::reply::json(&json!({ "replays": ids })))
+}
+
+// Runs `simulate` on the blocking pool, seeking and stepping re-simulate ticks and
+// must not hold up the async workers. None if the task panicked.
+async fn simulate_off_worker<T: Send + 'static>(
+    mut player: ReplayPlayer,
+    simulate: impl FnOnce(&mut ReplayPlayer) -> T + Send + 'static,
+) -> Option<(ReplayPlayer, T)> {
+    tokio::task::spawn_blocking(move || {
+        let result = simulate(&mut player);
+        (player, result)
+    }).await.ok()
+}
+
+pub async fn handle_replay_websocket(ws: WebSocket, log: ReplayLog, format: WireFormat) {
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let tick_interval_ms = log.config.update_interval_ms as f64;
+    let mut player = ReplayPlayer::new(log);
+    let mut paused = false;
+    let mut speed = 1.0;
+    let mut send_frame = Some(Frame::Keyframe);
+    // Terrain and the nations' names and colours go ahead of the first keyframe
+    let mut send_setup = Some([terrain(player.state()), roster(player.state())]);
+
+    loop {
+        if let Some(kind) = send_frame {
+            let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "send_replay_frame");
+            let frame = match kind {
+                Frame::Keyframe => keyframe(player.state(), format),
+                Frame::Delta => delta(player.state(), format),
+            };
+            let mut sent = true;
+            for message in send_setup.take().into_iter().flatten().chain([frame]) {
+                sent = sent && ws_tx.send(message).await.is_ok();
+            }
+            if !sent {
+                break;
+            }
+        }
+        let status = ReplayStatus {
+            kind: "replay_status",
+            tick: player.tick(),
+            total_ticks: player.log().total_ticks(),
+            paused,
+            speed,
+        };
+        if ws_tx.send(Message::text(serde_json::to_string(&status).unwrap())).await.is_err() {
+            break;
+        }
+
+        let playing = !paused && !player.is_at_end();
+        let frame_delay = Duration::from_secs_f64(tick_interval_ms / 1000.0 / speed);
+        send_frame = None;
+
+        tokio::select! {
+            incoming = ws_rx.next() => {
+                let msg = match incoming {
+                    Some(Ok(msg)) if !msg.is_close() => msg,
+                    _ => break,
+                };
+                let Some(control) = msg.to_str().ok().and_then(|text| serde_json::from_str(text).ok()) else {
+                    continue;
+                };
+                match control {
+                    ReplayControl::Play => paused = false,
+                    ReplayControl::Pause => paused = true,
+                    ReplayControl::Speed { speed: requested } => speed = requested.clamp(MIN_SPEED, MAX_SPEED),
+                    ReplayControl::Seek { tick } => {
+                        let Some((seeked, ())) = simulate_off_worker(player, move |player| player.seek(tick)).await else {
+                            break;
+                        };
+                        player = seeked;
+                        send_frame = Some(Frame::Keyframe);
+                    }
+                    ReplayControl::Resync => {
+                        send_setup = Some([terrain(player.state()), roster(player.state())]);
+                        send_frame = Some(Frame::Keyframe);
+                    }
+                }
+            }
+            _ = sleep(frame_delay), if playing => {
+                let Some((stepped, moved)) = simulate_off_worker(player, |player| player.step()).await else {
+                    break;
+                };
+                player = stepped;
+                if moved {
+                    send_frame = Some(Frame::Delta);
+                }
+            }
+            _ = sleep(PAUSED_STATUS_INTERVAL), if !playing => {}
+        }
+    }
+
+    let _ = ws_tx.send(Message::close()).await;
+}
+
+pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // The page is the regular client, which switches to replay mode based on the URL
+    let page = warp::path!("replay" / String)
+        .and(warp::get())
+        .and(warp::fs::file("src/web/index.html"))
+        .map(|_id: String, page: warp::filters::fs::File| page);
+
+    let socket = warp::path!("replay" / String / "ws")
+        .and(warp::ws())
+        .and(warp::query::<SocketOptions>())
+        .and_then(|id: String, ws: warp::ws::Ws, options: SocketOptions| async move {
+            match load_replay(&id).await {
+                Some(log) => Ok(ws.on_upgrade(move |socket| handle_replay_websocket(socket, log, options.format))),
+                None => Err(warp::reject::not_found()),
+            }
+        });
+
+    let list = warp::path!("api" / "replays")
+        .and(warp::get())
+        .and_then(list_replays);
+
+    page.or(socket).or(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::game::testing::test_game;
+
+    #[tokio::test]
+    async fn replays_seek_off_the_async_worker() {
+        let mut game = test_game(60, 40, 4, 9);
+        game.start_recording();
+        for _ in 0..30 {
+            game.update();
+        }
+        let player = ReplayPlayer::new(game.replay_log().unwrap());
+        let (player, ()) = simulate_off_worker(player, |player| player.seek(30)).await.unwrap();
+        assert_eq!(player.state().grid, game.grid);
+        let (player, moved) = simulate_off_worker(player, |player| player.step()).await.unwrap();
+        assert!(!moved && player.is_at_end());
+    }
+}
@@ -0,0 +1,718 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::modules::config::{ConfigError, GameConfig};
+use crate::modules::game::{GameState, LifecycleEvent, Phase, StatsTracker, STATS_HISTORY};
+use crate::modules::map::MapError;
+use crate::modules::timing::ExecutionTimer;
+use crate::TIMING_STATS;
+use super::admin::reply;
+use super::protocol::{encode_update, event_messages, roster_message, stats_message, EncodedUpdate, SocketOptions};
+use super::replay::save_replay;
+
+// Room behind `/ws`, it is never torn down
+pub const DEFAULT_ROOM: &str = "default";
+pub const MAX_ROOMS: usize = 16;
+
+// Rooms nobody watches are closed after this long, and rooms whose game ended
+// without a restart after FINISHED_ROOM_TIMEOUT, checked every REAP_INTERVAL
+const ROOM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const FINISHED_ROOM_TIMEOUT: Duration = Duration::from_secs(120);
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+
+const BROADCAST_CAPACITY: usize = 8192;
+
+#[derive(Debug)]
+pub enum RoomError {
+    InvalidId(String),
+    Exists(String),
+    NotFound(String),
+    TooMany,
+    Permanent(String),
+    Config(ConfigError),
+    Map(MapError),
+}
+
+impl fmt::Display for RoomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomError::InvalidId(id) => write!(f, "invalid room id '{}'", id),
+            RoomError::Exists(id) => write!(f, "room '{}' already exists", id),
+            RoomError::NotFound(id) => write!(f, "no room '{}'", id),
+            RoomError::TooMany => write!(f, "the server hosts at most {} rooms", MAX_ROOMS),
+            RoomError::Permanent(id) => write!(f, "room '{}' cannot be closed", id),
+            RoomError::Config(err) => write!(f, "{}", err),
+            RoomError::Map(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for RoomError {}
+
+impl RoomError {
+    fn status(&self) -> StatusCode {
+        match self {
+            RoomError::NotFound(_) => StatusCode::NOT_FOUND,
+            RoomError::Exists(_) | RoomError::TooMany | RoomError::Permanent(_) => StatusCode::CONFLICT,
+            RoomError::InvalidId(_) | RoomError::Config(_) | RoomError::Map(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+// Room ids end up in URLs, keep them to a safe character set
+fn valid_room_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 32 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// One game with its own tick task and broadcast channel. The task stops when
+// the room is dropped, which ends the broadcast streams of its clients.
+pub struct Room {
+    pub id: String,
+    pub state: Arc<RwLock<GameState>>,
+    // Fed by the tick task after every tick
+    pub stats: Arc<Mutex<StatsTracker>>,
+    tx: broadcast::Sender<Arc<EncodedUpdate>>,
+    connections: Arc<AtomicUsize>,
+    permanent: bool,
+    ticker: JoinHandle<()>,
+    // Nations the lobby reserved for matched players
+    seats: Mutex<Vec<Seat>>,
+    // When the reaper first saw the room unwatched, or its game over for good
+    idle_since: Mutex<Option<Instant>>,
+    finished_since: Mutex<Option<Instant>>,
+}
+
+// A nation held for a player from the lobby, who claims it by connecting with
+// the token
+#[derive(Clone, Debug, Serialize)]
+pub struct Seat {
+    pub player: usize,
+    pub name: String,
+    pub color: String,
+    #[serde(skip)]
+    pub token: String,
+}
+
+impl Drop for Room {
+    fn drop(&mut self) {
+        self.ticker.abort();
+    }
+}
+
+// Counts a client as watching a room for as long as it is alive
+pub struct Connection {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Room {
+    fn new(id: String, state: GameState, permanent: bool) -> Self {
+        let update_interval_ms = state.config.update_interval_ms;
+        let state = Arc::new(RwLock::new(state));
+        let stats = Arc::new(Mutex::new(StatsTracker::new()));
+        let (tx, _rx) = broadcast::channel(BROADCAST_CAPACITY);
+        let ticker = tokio::spawn(run_room(id.clone(), state.clone(), stats.clone(), tx.clone(), update_interval_ms));
+        Room {
+            id,
+            state,
+            stats,
+            tx,
+            connections: Arc::new(AtomicUsize::new(0)),
+            permanent,
+            ticker,
+            seats: Mutex::new(Vec::new()),
+            idle_since: Mutex::new(None),
+            finished_since: Mutex::new(None),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<EncodedUpdate>> {
+        self.tx.subscribe()
+    }
+
+    pub fn connect(&self) -> Connection {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        Connection { connections: self.connections.clone() }
+    }
+
+    pub fn spectators(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn reserve(&self, seat: Seat) {
+        self.seats.lock().unwrap().push(seat);
+    }
+
+    // The seat reserved under `token`
+    pub fn seat(&self, token: &str) -> Option<Seat> {
+        self.seats.lock().unwrap().iter().find(|seat| seat.token == token).cloned()
+    }
+
+    pub async fn info(&self) -> RoomInfo {
+        let state = self.state.read().await;
+        RoomInfo {
+            id: self.id.clone(),
+            phase: state.phase,
+            tick: state.tick,
+            players: state.players.len(),
+            num_players: state.config.num_players,
+            grid_width: state.config.grid_width,
+            grid_height: state.config.grid_height,
+            spectators: self.spectators(),
+            seats: self.seats.lock().unwrap().clone(),
+        }
+    }
+
+    // Whether the reaper should close the room, keeping track of how long it
+    // has been unwatched or over
+    fn expired(&self, phase: Phase, now: Instant) -> bool {
+        if self.permanent {
+            return false;
+        }
+        let since = |slot: &Mutex<Option<Instant>>, active: bool| {
+            let mut slot = slot.lock().unwrap();
+            if !active {
+                *slot = None;
+                return Duration::ZERO;
+            }
+            now - *slot.get_or_insert(now)
+        };
+        let idle = since(&self.idle_since, self.spectators() == 0);
+        let finished = since(&self.finished_since, phase == Phase::Finished { remaining: None });
+        idle >= ROOM_IDLE_TIMEOUT || finished >= FINISHED_ROOM_TIMEOUT
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RoomInfo {
+    pub id: String,
+    #[serde(flatten)]
+    pub phase: Phase,
+    pub tick: u64,
+    // Nations still in the game, out of `num_players`
+    pub players: usize,
+    pub num_players: usize,
+    pub grid_width: usize,
+    pub grid_height: usize,
+    pub spectators: usize,
+    pub seats: Vec<Seat>,
+}
+
+// Body of `POST /api/rooms`. `config` holds overrides on top of the server's
+// configuration, rooms get a seed of their own unless it sets one.
+#[derive(Default, Deserialize)]
+pub struct CreateRoom {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub config: Option<Value>,
+}
+
+pub struct RoomManager {
+    base_config: GameConfig,
+    rooms: RwLock<BTreeMap<String, Arc<Room>>>,
+    next_id: AtomicU64,
+}
+
+impl RoomManager {
+    pub fn new(base_config: GameConfig) -> Self {
+        RoomManager { base_config, rooms: RwLock::new(BTreeMap::new()), next_id: AtomicU64::new(1) }
+    }
+
+    // Hosts an existing game as the permanent room behind `/ws`
+    pub async fn open_default(&self, state: GameState) -> Arc<Room> {
+        let room = Arc::new(Room::new(DEFAULT_ROOM.to_string(), state, true));
+        self.rooms.write().await.insert(room.id.clone(), room.clone());
+        room
+    }
+
+    // Server configuration new rooms start from
+    pub fn config(&self) -> &GameConfig {
+        &self.base_config
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Arc<Room>> {
+        self.rooms.read().await.get(id).cloned()
+    }
+
+    pub async fn list(&self) -> Vec<RoomInfo> {
+        let rooms: Vec<Arc<Room>> = self.rooms.read().await.values().cloned().collect();
+        let mut infos = Vec::with_capacity(rooms.len());
+        for room in rooms {
+            infos.push(room.info().await);
+        }
+        infos
+    }
+
+    pub async fn create(&self, request: CreateRoom) -> Result<Arc<Room>, RoomError> {
+        let config = self.room_config(request.config)?;
+        let id = match request.id {
+            Some(id) if !valid_room_id(&id) => return Err(RoomError::InvalidId(id)),
+            Some(id) => id,
+            None => format!("room-{}", self.next_id.fetch_add(1, Ordering::Relaxed)),
+        };
+        {
+            let rooms = self.rooms.read().await;
+            if rooms.contains_key(&id) {
+                return Err(RoomError::Exists(id));
+            }
+            if rooms.len() >= MAX_ROOMS {
+                return Err(RoomError::TooMany);
+            }
+        }
+
+        // Generating a map takes a while, keep it off the async workers
+        let state = tokio::task::spawn_blocking(move || {
+            let mut state = GameState::from_config(config)?;
+            state.initialize_players();
+            state.start_recording();
+            state.open_lobby();
+            Ok::<_, MapError>(state)
+        }).await.expect("room setup does not panic").map_err(RoomError::Map)?;
+
+        let mut rooms = self.rooms.write().await;
+        if rooms.contains_key(&id) {
+            return Err(RoomError::Exists(id));
+        }
+        if rooms.len() >= MAX_ROOMS {
+            return Err(RoomError::TooMany);
+        }
+        let room = Arc::new(Room::new(id.clone(), state, false));
+        rooms.insert(id.clone(), room.clone());
+        println!("Opened room '{}'", id);
+        Ok(room)
+    }
+
+    // Stops the room's game, its clients are disconnected once their streams end
+    pub async fn close(&self, id: &str) -> Result<(), RoomError> {
+        let mut rooms = self.rooms.write().await;
+        match rooms.get(id) {
+            None => return Err(RoomError::NotFound(id.to_string())),
+            Some(room) if room.permanent => return Err(RoomError::Permanent(id.to_string())),
+            Some(_) => {}
+        }
+        rooms.remove(id);
+        println!("Closed room '{}'", id);
+        Ok(())
+    }
+
+    // Closes rooms nobody has watched for a while and rooms whose game ended
+    // without a restart
+    pub async fn reap(&self) {
+        self.reap_at(Instant::now()).await;
+    }
+
+    async fn reap_at(&self, now: Instant) {
+        let rooms: Vec<Arc<Room>> = self.rooms.read().await.values().cloned().collect();
+        for room in rooms {
+            let phase = room.state.read().await.phase;
+            if room.expired(phase, now) {
+                let _ = self.close(&room.id).await;
+            }
+        }
+    }
+
+    fn room_config(&self, overrides: Option<Value>) -> Result<GameConfig, RoomError> {
+        let invalid = |reason: String| RoomError::Config(ConfigError::Invalid(reason));
+        let mut config = serde_json::to_value(GameConfig { seed: None, ..self.base_config.clone() })
+            .map_err(|e| invalid(e.to_string()))?;
+        match overrides {
+            None => {}
+            Some(Value::Object(overrides)) => {
+                let fields = config.as_object_mut().expect("the configuration is an object");
+                for (key, value) in overrides {
+                    if !fields.contains_key(&key) {
+                        return Err(invalid(format!("unknown setting '{}'", key)));
+                    }
+                    fields.insert(key, value);
+                }
+            }
+            Some(_) => return Err(invalid("config must be an object".into())),
+        }
+        let config: GameConfig = serde_json::from_value(config).map_err(|e| invalid(e.to_string()))?;
+        config.validate().map_err(RoomError::Config)?;
+        Ok(config)
+    }
+}
+
+pub async fn run_reaper(manager: Arc<RoomManager>) {
+    let mut interval = time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        manager.reap().await;
+    }
+}
+
+// Ticks a room's game and broadcasts every update to its clients
+async fn run_room(
+    id: String,
+    state: Arc<RwLock<GameState>>,
+    stats: Arc<Mutex<StatsTracker>>,
+    tx: broadcast::Sender<Arc<EncodedUpdate>>,
+    update_interval_ms: u64,
+) {
+    let mut interval = time::interval(Duration::from_millis(update_interval_ms));
+    let mut last_broadcast_tick = None;
+    let mut last_roster = None;
+    loop {
+        interval.tick().await;
+        {
+            let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "game_state_update");
+            let mut state = state.write().await;
+            if let Err(e) = state.step() {
+                println!("Room '{}' could not start a new game: {}", id, e);
+            }
+
+            // Broadcast the cells that changed, or a keyframe when due, after
+            // the events of the tick
+            let lifecycle = state.take_lifecycle_events();
+            let diplomacy = state.take_diplomacy_events();
+            // A new game, restarted or restored from a snapshot, starts with a
+            // keyframe whatever its tick
+            if lifecycle.contains(&LifecycleEvent::NewGame) {
+                last_broadcast_tick = None;
+            }
+            let mut events = event_messages(&state, &diplomacy, &lifecycle);
+            events.extend(roster_update(&state, &mut last_roster));
+            let mut stats = stats.lock().unwrap();
+            if stats.observe(&state) {
+                events.extend(stats_message(&stats));
+            }
+            drop(stats);
+            let update = encode_update(&state, last_broadcast_tick, events);
+            last_broadcast_tick = Some(state.tick);
+            let _ = tx.send(Arc::new(update));
+
+            // Keep the recording of each finished game, the grid no longer changes afterwards
+            for event in &lifecycle {
+                let LifecycleEvent::GameOver(result) = event else {
+                    continue;
+                };
+                println!(
+                    "Room '{}': game over at tick {} ({:?}), won by {:?}",
+                    id, result.tick, result.reason, result.winners
+                );
+                if let Some(log) = state.replay_log() {
+                    tokio::spawn(async move {
+                        match save_replay(log).await {
+                            Ok(id) => println!("Replay available at /replay/{}", id),
+                            Err(e) => println!("Could not save replay: {}", e),
+                        }
+                    });
+                }
+            }
+        }
+
+        // Small delay to prevent tight loops
+        time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+// Claims, renames and eliminations all show in the roster, it goes out whenever
+// it is no longer what clients last got
+fn roster_update(state: &GameState, last_roster: &mut Option<String>) -> Option<String> {
+    let roster = roster_message(state);
+    if last_roster.as_ref() == Some(&roster) {
+        return None;
+    }
+    *last_roster = Some(roster.clone());
+    Some(roster)
+}
+
+// Query of `GET /api/stats`, the default room and the whole history unless given
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    #[serde(default)]
+    pub room: Option<String>,
+    #[serde(default)]
+    pub window: Option<usize>,
+}
+
+async fn room_stats(query: StatsQuery, manager: Arc<RoomManager>) -> Result<warp::reply::Response, Rejection> {
+    let id = query.room.unwrap_or_else(|| DEFAULT_ROOM.to_string());
+    let Some(room) = manager.get(&id).await else {
+        return Ok(reply(StatusCode::NOT_FOUND, json!({ "error": RoomError::NotFound(id).to_string() })));
+    };
+    let window = query.window.unwrap_or(STATS_HISTORY).min(STATS_HISTORY);
+    let stats = room.stats.lock().unwrap();
+    Ok(reply(StatusCode::OK, json!({
+        "room": id,
+        "tick": stats.tick(),
+        "players": stats.current(),
+        "history": stats.history(window),
+    })))
+}
+
+async fn list_rooms(manager: Arc<RoomManager>) -> Result<warp::reply::Response, Rejection> {
+    Ok(reply(StatusCode::OK, json!({ "rooms": manager.list().await })))
+}
+
+async fn create_room(request: CreateRoom, manager: Arc<RoomManager>) -> Result<warp::reply::Response, Rejection> {
+    match manager.create(request).await {
+        Ok(room) => Ok(reply(StatusCode::CREATED, json!(room.info().await))),
+        Err(e) => Ok(reply(e.status(), json!({ "error": e.to_string() }))),
+    }
+}
+
+async fn close_room(id: String, manager: Arc<RoomManager>) -> Result<warp::reply::Response, Rejection> {
+    match manager.close(&id).await {
+        Ok(()) => Ok(reply(StatusCode::OK, json!({ "id": id }))),
+        Err(e) => Ok(reply(e.status(), json!({ "error": e.to_string() }))),
+    }
+}
+
+async fn join_room(
+    id: String,
+    ws: warp::ws::Ws,
+    options: SocketOptions,
+    manager: Arc<RoomManager>,
+) -> Result<warp::reply::Response, Rejection> {
+    let Some(room) = manager.get(&id).await else {
+        return Err(warp::reject::not_found());
+    };
+    let seat = match options.seat.as_deref().map(|token| room.seat(token)) {
+        None => None,
+        Some(Some(seat)) => Some(seat),
+        Some(None) => return Ok(reply(StatusCode::FORBIDDEN, json!({ "error": "unknown seat" }))),
+    };
+    // Only the state and a receiver go to the connection, so closing the room
+    // ends its stream
+    let (state, stats, rx, connection) = (room.state.clone(), room.stats.clone(), room.subscribe(), room.connect());
+    Ok(ws.on_upgrade(move |socket| async move {
+        super::handle_websocket(socket, state, stats, rx, options.format, seat).await;
+        drop(connection);
+    }).into_response())
+}
+
+pub fn routes(manager: Arc<RoomManager>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let with_manager = warp::any().map(move || manager.clone());
+
+    let default_socket = warp::path("ws")
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(warp::query::<SocketOptions>())
+        .and(with_manager.clone())
+        .and_then(|ws, options, manager| join_room(DEFAULT_ROOM.to_string(), ws, options, manager));
+
+    let socket = warp::path!("ws" / String)
+        .and(warp::ws())
+        .and(warp::query::<SocketOptions>())
+        .and(with_manager.clone())
+        .and_then(join_room);
+
+    // The page is the regular client, which picks the room from the URL
+    let page = warp::path!("room" / String)
+        .and(warp::get())
+        .and(warp::fs::file("src/web/index.html"))
+        .map(|_id: String, page: warp::filters::fs::File| page);
+
+    let list = warp::path!("api" / "rooms")
+        .and(warp::get())
+        .and(with_manager.clone())
+        .and_then(list_rooms);
+
+    let create = warp::path!("api" / "rooms")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json())
+        .and(with_manager.clone())
+        .and_then(create_room);
+
+    let stats = warp::path!("api" / "stats")
+        .and(warp::get())
+        .and(warp::query::<StatsQuery>())
+        .and(with_manager.clone())
+        .and_then(room_stats);
+
+    let close = warp::path!("api" / "rooms" / String)
+        .and(warp::delete())
+        .and(with_manager)
+        .and_then(close_room);
+
+    default_socket.or(socket).or(page).or(list).or(create).or(close).or(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::game::testing::{test_config, test_game};
+    use crate::modules::game::PlayerCommand;
+
+    fn manager() -> RoomManager {
+        RoomManager::new(test_config(60, 40, 4, 9))
+    }
+
+    #[test]
+    fn rooms_override_the_server_config() {
+        let manager = manager();
+        let config = manager.room_config(Some(json!({ "num_players": 2, "time_limit_ticks": 50 }))).unwrap();
+        assert_eq!((config.num_players, config.time_limit_ticks, config.grid_width), (2, 50, 60));
+        // Each room plays its own game unless told otherwise
+        assert_eq!(config.seed, None);
+
+        assert!(matches!(manager.room_config(Some(json!({ "players": 2 }))), Err(RoomError::Config(_))));
+        assert!(matches!(manager.room_config(Some(json!({ "num_players": 0 }))), Err(RoomError::Config(_))));
+        assert!(!valid_room_id("../etc") && !valid_room_id("") && valid_room_id("team-b_2"));
+    }
+
+    #[tokio::test]
+    async fn rooms_are_created_listed_and_closed() {
+        let manager = manager();
+        manager.open_default(GameState::new(manager.base_config.clone())).await;
+        let room = manager.create(CreateRoom { id: Some("duel".into()), ..CreateRoom::default() }).await.unwrap();
+        let mut updates = room.subscribe();
+        assert!(updates.recv().await.is_ok());
+
+        let create_again = manager.create(CreateRoom { id: Some("duel".into()), ..CreateRoom::default() }).await;
+        assert!(matches!(create_again, Err(RoomError::Exists(_))));
+        let ids: Vec<String> = manager.list().await.into_iter().map(|info| info.id).collect();
+        assert_eq!(ids, vec!["default", "duel"]);
+
+        assert!(matches!(manager.close(DEFAULT_ROOM).await, Err(RoomError::Permanent(_))));
+        manager.close("duel").await.unwrap();
+        drop(room);
+        // With the room gone its tick task stops and the channel closes
+        while let Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) = updates.recv().await {}
+        assert!(manager.get("duel").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn idle_and_finished_rooms_are_reaped() {
+        let manager = manager();
+        manager.open_default(GameState::new(manager.base_config.clone())).await;
+        let create = |id: &str| CreateRoom { id: Some(id.into()), ..CreateRoom::default() };
+        manager.create(create("empty")).await.unwrap();
+        let over = manager.create(create("over")).await.unwrap();
+        let watched = manager.create(create("watched")).await.unwrap();
+        let _over_client = over.connect();
+        let _watched_client = watched.connect();
+        over.state.write().await.phase = Phase::Finished { remaining: None };
+        drop((over, watched));
+
+        let start = Instant::now();
+        let ids = || async { manager.list().await.into_iter().map(|info| info.id).collect::<Vec<_>>() };
+        manager.reap_at(start).await;
+        assert_eq!(ids().await, vec!["default", "empty", "over", "watched"]);
+        manager.reap_at(start + ROOM_IDLE_TIMEOUT).await;
+        assert_eq!(ids().await, vec!["default", "over", "watched"]);
+        manager.reap_at(start + FINISHED_ROOM_TIMEOUT).await;
+        // Watched rooms with a game going on and the default room stay open
+        assert_eq!(ids().await, vec!["default", "watched"]);
+    }
+
+    #[test]
+    fn the_roster_is_only_sent_when_it_changes() {
+        let mut state = test_game(60, 40, 4, 9);
+        let mut last_roster = None;
+        assert!(roster_update(&state, &mut last_roster).is_some());
+        state.update();
+        assert_eq!(roster_update(&state, &mut last_roster), None);
+
+        state.apply_command(2, PlayerCommand::Claim).unwrap();
+        let claimed = roster_update(&state, &mut last_roster).expect("claims show in the roster");
+        assert!(claimed.contains("\"human\""));
+        state.set_identity(2, "Ada", "#123456").unwrap();
+        assert!(roster_update(&state, &mut last_roster).is_some_and(|roster| roster.contains("Ada")));
+        state.players.retain(|p| p.id != 3);
+        assert!(roster_update(&state, &mut last_roster).is_some());
+        assert_eq!(roster_update(&state, &mut last_roster), None);
+    }
+
+    #[tokio::test]
+    async fn stats_are_served_per_room_and_window() {
+        let manager = Arc::new(manager());
+        // The room ticks once as it opens, then stays put while the test feeds its stats
+        let config = GameConfig { update_interval_ms: 3_600_000, ..manager.base_config.clone() };
+        let room = manager.open_default(GameState::new(config)).await;
+        let mut updates = room.subscribe();
+        assert!(updates.recv().await.is_ok());
+        let mut game = test_game(60, 40, 4, 9);
+        {
+            let mut stats = room.stats.lock().unwrap();
+            stats.observe(&game);
+            for _ in 0..STATS_HISTORY + 10 {
+                game.update();
+                stats.observe(&game);
+            }
+        }
+
+        let routes = routes(manager.clone());
+        let get = |path: &'static str| warp::test::request().method("GET").path(path).reply(&routes);
+        let history_lengths = |body: &[u8]| -> Vec<usize> {
+            let body: Value = serde_json::from_slice(body).unwrap();
+            body["history"].as_array().unwrap().iter().map(|h| h["area"].as_array().unwrap().len()).collect()
+        };
+
+        let response = get("/api/stats?window=5").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!((body["room"].as_str(), body["tick"].as_u64()), (Some(DEFAULT_ROOM), Some(game.tick)));
+        assert_eq!(body["players"].as_array().unwrap().len(), game.players.len());
+        assert!(history_lengths(response.body()).iter().all(|&len| len == 5));
+        // Windows past the kept history get all of it
+        for path in ["/api/stats?window=100000", "/api/stats"] {
+            assert!(history_lengths(get(path).await.body()).iter().all(|&len| len == STATS_HISTORY));
+        }
+
+        let missing = get("/api/stats?room=nowhere").await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        let body: Value = serde_json::from_slice(missing.body()).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("nowhere"));
+    }
+
+    #[tokio::test]
+    async fn restored_games_start_with_a_keyframe() {
+        let manager = manager();
+        let mut game = test_game(60, 40, 4, 9);
+        game.config.update_interval_ms = 20;
+        let room = manager.open_default(game).await;
+        let mut updates = room.subscribe();
+        assert!(updates.recv().await.is_ok());
+
+        // Another game at the very tick the room is at, so its next tick follows
+        // on from the last one broadcast
+        let restored_tick = {
+            let mut state = room.state.write().await;
+            let mut other = test_game(60, 40, 4, 10);
+            while other.tick < state.tick {
+                other.update();
+            }
+            let other = GameState::from_snapshot_str(&other.to_snapshot_string().unwrap()).unwrap();
+            state.restore(other);
+            state.tick
+        };
+
+        let update = loop {
+            let update = updates.recv().await.unwrap();
+            let Some((json, _)) = &update.grid else {
+                continue;
+            };
+            let grid: Value = serde_json::from_str(json).unwrap();
+            if grid["seq"].as_u64() > Some(restored_tick) {
+                break update;
+            }
+        };
+        let grid: Value = serde_json::from_str(&update.grid.as_ref().unwrap().0).unwrap();
+        assert_eq!(grid["type"], "keyframe");
+        assert_eq!(grid["seq"].as_u64(), Some(restored_tick + 1));
+        let kinds: Vec<String> = update.events.iter()
+            .map(|event| serde_json::from_str::<Value>(event).unwrap()["type"].as_str().unwrap().to_string())
+            .collect();
+        assert!(kinds.contains(&"terrain".to_string()) && kinds.contains(&"teams".to_string()), "{:?}", kinds);
+    }
+}
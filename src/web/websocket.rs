@@ -7,19 +7,78 @@ use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::time::{sleep, Duration};
 
-use crate::modules::game::GameState;
+use crate::modules::game::{CommandError, GameState, PlayerCommand, StatsTracker};
+use super::protocol::{
+    game_over, keyframe, leaderboard, phase, roster, stats_history, teams, terrain, treaties, ClientMessage, EncodedUpdate,
+    ServerMessage, WireFormat,
+};
+use super::rooms::Seat;
 use crate::modules::timing::ExecutionTimer;
 use crate::TIMING_STATS;
 
+// Applies a player command for this connection, which controls at most one nation
+async fn handle_command(
+    game_state: &RwLock<GameState>,
+    claimed: &mut Option<usize>,
+    message: ClientMessage,
+) -> Result<Message, CommandError> {
+    let mut state = game_state.write().await;
+    let reply = match message {
+        ClientMessage::Claim { player } => {
+            state.apply_command(player, PlayerCommand::Claim)?;
+            // Switching nations hands the previous one back to the bots
+            if let Some(previous) = claimed.replace(player) {
+                let _ = state.apply_command(previous, PlayerCommand::Release);
+            }
+            println!("Player {} is now controlled by a client", player);
+            ServerMessage::Claimed { player }
+        }
+        ClientMessage::Release => {
+            let player = claimed.take().ok_or(CommandError::NoPlayer)?;
+            let _ = state.apply_command(player, PlayerCommand::Release);
+            ServerMessage::Released { player }
+        }
+        ClientMessage::Attack { target, percent, toward } => {
+            let player = claimed.ok_or(CommandError::NoPlayer)?;
+            state.apply_command(player, PlayerCommand::Attack { target, percent, toward })?;
+            ServerMessage::AttackStarted { target, percent }
+        }
+        ClientMessage::NavalAttack { x, y, percent } => {
+            let player = claimed.ok_or(CommandError::NoPlayer)?;
+            state.apply_command(player, PlayerCommand::NavalAttack { x, y, percent })?;
+            ServerMessage::NavalAttackStarted { x, y, percent }
+        }
+        // Everyone hears about the treaty with the next tick, the sender gets
+        // the new state of diplomacy straight away
+        ClientMessage::ProposeTreaty { to, treaty } => {
+            let player = claimed.ok_or(CommandError::NoPlayer)?;
+            state.apply_command(player, PlayerCommand::ProposeTreaty { to, treaty })?;
+            return Ok(treaties(&state));
+        }
+        ClientMessage::AcceptTreaty { from } => {
+            let player = claimed.ok_or(CommandError::NoPlayer)?;
+            state.apply_command(player, PlayerCommand::AcceptTreaty { from })?;
+            return Ok(treaties(&state));
+        }
+        ClientMessage::BreakTreaty { with } => {
+            let player = claimed.ok_or(CommandError::NoPlayer)?;
+            state.apply_command(player, PlayerCommand::BreakTreaty { with })?;
+            return Ok(treaties(&state));
+        }
+        ClientMessage::Resync => unreachable!("resync is not a player command"),
+    };
+    Ok(reply.to_text())
+}
+
 pub async fn handle_websocket(
     ws: WebSocket,
     game_state: Arc<RwLock<GameState>>,
-    tx: Arc<broadcast::Sender<String>>
+    stats: Arc<std::sync::Mutex<StatsTracker>>,
+    rx: broadcast::Receiver<Arc<EncodedUpdate>>,
+    format: WireFormat,
+    seat: Option<Seat>,
 ) {
     let (mut ws_tx, mut ws_rx) = ws.split();
-
-    // Create a new subscriber and keep the receiver around
-    let rx = tx.subscribe();
     let mut rx_stream = BroadcastStream::new(rx);
 
     // Increased channel capacity to prevent backpressure
@@ -29,30 +88,91 @@ pub async fn handle_websocket(
     {
         let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "send_initial_state");
         let state = game_state.read().await;
-        let initial_state = serde_json::to_string(&state.grid).unwrap();
-        if let Err(_) = ws_tx.send(Message::text(initial_state)).await {
+        // The result goes ahead of the phase so the banner can name the winner
+        let history = stats_history(&stats.lock().unwrap());
+        let messages = [terrain(&state), teams(&state), roster(&state), treaties(&state)].into_iter()
+            .chain(game_over(&state))
+            .chain([phase(&state), leaderboard(&state)])
+            .chain(history)
+            .chain([keyframe(&state, format)]);
+        for message in messages {
+            if ws_tx.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // Players matched by the lobby take their seat straight away, under the
+    // name and colour they picked
+    let mut claimed = None;
+    if let Some(Seat { player, name, color, .. }) = seat {
+        let mut state = game_state.write().await;
+        let claim = state.apply_command(player, PlayerCommand::Claim)
+            .and_then(|()| state.set_identity(player, &name, &color));
+        drop(state);
+        let reply = match claim {
+            Ok(()) => {
+                claimed = Some(player);
+                ServerMessage::Claimed { player }
+            }
+            Err(e) => ServerMessage::Error { message: e.to_string() },
+        };
+        if ws_tx.send(reply.to_text()).await.is_err() {
+            if claimed.is_some() {
+                let _ = game_state.write().await.apply_command(player, PlayerCommand::Release);
+            }
             return;
         }
     }
 
     // Handle incoming messages in a separate task
     let msg_tx_clone = msg_tx.clone();
+    let game_state_incoming = game_state.clone();
     tokio::spawn(async move {
+        let mut claimed = claimed;
         while let Some(result) = ws_rx.next().await {
             let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "handle_incoming_message");
             match result {
                 Ok(msg) => {
                     if msg.is_ping() {
-                        if let Err(_) = msg_tx_clone.send(Message::pong(vec![])).await {
+                        if msg_tx_clone.send(Message::pong(vec![])).await.is_err() {
                             break;
                         }
                     } else if msg.is_close() {
                         break;
+                    } else if let Ok(text) = msg.to_str() {
+                        let reply = match serde_json::from_str(text) {
+                            // A client that missed a delta gets the terrain and a fresh keyframe
+                            Ok(ClientMessage::Resync) => {
+                                let state = game_state_incoming.read().await;
+                                if msg_tx_clone.send(terrain(&state)).await.is_err()
+                                    || msg_tx_clone.send(teams(&state)).await.is_err()
+                                    || msg_tx_clone.send(roster(&state)).await.is_err()
+                                    || msg_tx_clone.send(treaties(&state)).await.is_err() {
+                                    break;
+                                }
+                                keyframe(&state, format)
+                            }
+                            Ok(message) => match handle_command(&game_state_incoming, &mut claimed, message).await {
+                                Ok(reply) => reply,
+                                Err(e) => ServerMessage::Error { message: e.to_string() }.to_text(),
+                            },
+                            Err(e) => ServerMessage::Error { message: format!("invalid message: {}", e) }.to_text(),
+                        };
+                        if msg_tx_clone.send(reply).await.is_err() {
+                            break;
+                        }
                     }
                 }
                 Err(_) => break,
             }
         }
+
+        // Nations of disconnected clients go back to the bots
+        if let Some(player) = claimed {
+            let _ = game_state_incoming.write().await.apply_command(player, PlayerCommand::Release);
+            println!("Player {} was released by its client", player);
+        }
     });
 
     // Send game state updates
@@ -61,7 +181,7 @@ pub async fn handle_websocket(
         tokio::select! {
             Some(msg) = msg_rx.recv() => {
                 let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "send_message");
-                if let Err(_) = ws_tx.send(msg).await {
+                if ws_tx.send(msg).await.is_err() {
                     consecutive_errors += 1;
                     if consecutive_errors > 3 {
                         break;
@@ -70,18 +190,33 @@ pub async fn handle_websocket(
                     consecutive_errors = 0;
                 }
             }
-            Some(Ok(state_msg)) = rx_stream.next() => {
+            update = rx_stream.next() => {
+                // The stream ends when the room is closed, lagging behind skips updates
+                let Some(update) = update else {
+                    break;
+                };
+                let Ok(state_msg) = update else {
+                    continue;
+                };
                 let _timer = ExecutionTimer::new(TIMING_STATS.clone(), "broadcast_state");
                 
                 // Send a ping to check connection health
-                if let Err(_) = ws_tx.send(Message::ping(vec![])).await {
+                if ws_tx.send(Message::ping(vec![])).await.is_err() {
                     break;
                 }
 
                 // Small delay to ensure ping is processed
                 sleep(Duration::from_millis(10)).await;
 
-                if let Err(_) = ws_tx.send(Message::text(state_msg)).await {
+                for event in &state_msg.events {
+                    if ws_tx.send(Message::text(event.clone())).await.is_err() {
+                        consecutive_errors += 1;
+                    }
+                }
+                let Some(grid) = state_msg.to_message(format) else {
+                    continue;
+                };
+                if ws_tx.send(grid).await.is_err() {
                     consecutive_errors += 1;
                     if consecutive_errors > 3 {
                         break;